@@ -1,5 +1,23 @@
-from io import BufferedIOBase
-from typing import Sequence, Union
+import os
+import sys
+from io import BufferedIOBase, TextIOBase
+from pathlib import Path
+from typing import (
+    Callable,
+    Iterable,
+    Iterator,
+    Literal,
+    Optional,
+    Sequence,
+    Tuple,
+    Union,
+    overload,
+)
+
+if sys.version_info >= (3, 12):
+    from collections.abc import Buffer
+else:
+    from typing_extensions import Buffer
 
 from pyrage import passphrase, plugin, ssh, x25519
 from pyrage.plugin import IdentityPluginV1, RecipientPluginV1
@@ -8,8 +26,15 @@ from pyrage.ssh import Recipient as SSHRecipient
 from pyrage.x25519 import Identity as X25519Identity
 from pyrage.x25519 import Recipient as X25519Recipient
 
-_Identity = Union[SSHIdentity, X25519Identity, IdentityPluginV1]
-_Recipient = Union[SSHRecipient, X25519Recipient, RecipientPluginV1]
+_Identity = Union[SSHIdentity, X25519Identity, IdentityPluginV1, str]
+_Recipient = Union[SSHRecipient, X25519Recipient, RecipientPluginV1, str]
+_Format = Literal["auto", "binary", "armored"]
+_Path = Union[str, bytes, os.PathLike[str], os.PathLike[bytes]]
+_FileArg = Union[_Path, int]
+# Unlike `_FileArg`, `bytes` is data here, not a path.
+_DataOrPath = Union[Buffer, str, os.PathLike[str], os.PathLike[bytes], int]
+_Progress = Callable[[int, Optional[int]], object]
+_OutputType = Literal["bytes", "bytearray", "secret"]
 
 __all__ = (
     "ssh",
@@ -17,43 +42,392 @@ __all__ = (
     "passphrase",
     "plugin",
     "encrypt",
+    "encrypt_armored",
     "encrypt_file",
     "encrypt_io",
     "decrypt",
+    "decrypt_into",
     "decrypt_file",
     "decrypt_io",
+    "can_decrypt",
+    "verify",
+    "encrypt_iter",
+    "decrypt_iter",
+    "armor",
+    "dearmor",
+    "is_armored",
+    "parse_recipient",
+    "parse_identity",
+    "encrypt_files",
+    "decrypt_files",
+    "encrypt_tree",
+    "decrypt_tree",
+    "rewrap",
+    "reencrypt_file",
+    "reencrypt_io",
+    "main",
+    "BatchResult",
+    "DecryptInfo",
+    "SecretBuffer",
+    "EncryptIter",
+    "DecryptIter",
     "RecipientError",
     "IdentityError",
     "EncryptError",
     "DecryptError",
+    "CancelledError",
+    "LimitExceededError",
+    "CancellationToken",
 )
 
 class RecipientError(Exception): ...
 class IdentityError(Exception): ...
 class EncryptError(Exception): ...
 class DecryptError(Exception): ...
+class CancelledError(Exception): ...
+class LimitExceededError(DecryptError): ...
+
+class CancellationToken:
+    def __init__(self) -> None: ...
+    def cancel(self) -> None: ...
+    @property
+    def cancelled(self) -> bool: ...
+
+class DecryptInfo:
+    @property
+    def identity_index(self) -> int: ...
+    @property
+    def stanza_tag(self) -> Optional[str]: ...
+    @property
+    def stanza_count(self) -> int: ...
+
+class SecretBuffer:
+    def reveal(self) -> bytearray: ...
+    def close(self) -> None: ...
+    @property
+    def closed(self) -> bool: ...
+    def __len__(self) -> int: ...
+    def __enter__(self) -> SecretBuffer: ...
+    def __exit__(self, *args: object) -> Literal[False]: ...
+
+class EncryptIter(Iterator[bytes]):
+    def __iter__(self) -> EncryptIter: ...
+    def __next__(self) -> bytes: ...
+
+class DecryptIter(Iterator[bytes]):
+    def __iter__(self) -> DecryptIter: ...
+    def __next__(self) -> bytes: ...
+
+class BatchResult:
+    @property
+    def source(self) -> Path: ...
+    @property
+    def destination(self) -> Path: ...
+    @property
+    def error(self) -> Optional[BaseException]: ...
+    @property
+    def skipped(self) -> Optional[str]: ...
+    @property
+    def ok(self) -> bool: ...
 
 def encrypt(
-    plaintext: bytes,
+    plaintext: Buffer,
     recipients: Sequence[_Recipient],
     armored: bool = False,
 ) -> bytes: ...
+def encrypt_armored(plaintext: Buffer, recipients: Sequence[_Recipient]) -> str: ...
 def encrypt_file(
-    infile: str,
-    outfile: str,
+    infile: _FileArg,
+    outfile: _FileArg,
     recipients: Sequence[_Recipient],
     armored: bool = False,
+    *,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
 ) -> None: ...
 def encrypt_io(
     in_io: BufferedIOBase,
-    out_io: BufferedIOBase,
+    out_io: Union[BufferedIOBase, TextIOBase],
     recipients: Sequence[_Recipient],
     armored: bool = False,
+    *,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
 ) -> bytes: ...
-def decrypt(ciphertext: bytes, identities: Sequence[_Identity]) -> bytes: ...
+@overload
+def decrypt(
+    ciphertext: Union[Buffer, str],
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    return_info: Literal[False] = False,
+    output_type: _OutputType = "bytes",
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> Union[bytes, bytearray, SecretBuffer]: ...
+@overload
+def decrypt(
+    ciphertext: Union[Buffer, str],
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    return_info: Literal[True],
+    output_type: _OutputType = "bytes",
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> Tuple[Union[bytes, bytearray, SecretBuffer], DecryptInfo]: ...
+def decrypt_into(
+    ciphertext: Union[Buffer, str],
+    out_buffer: Buffer,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> int: ...
+@overload
 def decrypt_file(
-    infile: str, outfile: str, identities: Sequence[_Identity]
+    infile: _FileArg,
+    outfile: _FileArg,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
+    return_info: Literal[False] = False,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
 ) -> None: ...
+@overload
+def decrypt_file(
+    infile: _FileArg,
+    outfile: _FileArg,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
+    return_info: Literal[True],
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> DecryptInfo: ...
+@overload
 def decrypt_io(
-    in_io: BufferedIOBase, out_io: BufferedIOBase, identities: Sequence[_Identity]
+    in_io: Union[BufferedIOBase, TextIOBase],
+    out_io: BufferedIOBase,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    atomic: bool = False,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
+    return_info: Literal[False] = False,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> None: ...
+@overload
+def decrypt_io(
+    in_io: Union[BufferedIOBase, TextIOBase],
+    out_io: BufferedIOBase,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    atomic: bool = False,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
+    return_info: Literal[True],
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> DecryptInfo: ...
+@overload
+def can_decrypt(
+    data_or_path: _DataOrPath,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    return_index: Literal[False] = False,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> bool: ...
+@overload
+def can_decrypt(
+    data_or_path: _DataOrPath,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    return_index: Literal[True],
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> int: ...
+@overload
+def verify(
+    data_or_path: _DataOrPath,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
+    return_info: Literal[False] = False,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> None: ...
+@overload
+def verify(
+    data_or_path: _DataOrPath,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    threads: int = 1,
+    return_info: Literal[True],
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> DecryptInfo: ...
+def encrypt_iter(
+    chunks: Iterable[Buffer], recipients: Sequence[_Recipient], armored: bool = False
+) -> EncryptIter: ...
+def decrypt_iter(
+    chunks: Iterable[Buffer],
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> DecryptIter: ...
+def armor(data: bytes) -> bytes: ...
+def dearmor(data: bytes) -> bytes: ...
+def is_armored(data: bytes) -> bool: ...
+def parse_recipient(s: str) -> Union[SSHRecipient, X25519Recipient, RecipientPluginV1]: ...
+def parse_identity(s: str) -> Union[SSHIdentity, X25519Identity, IdentityPluginV1]: ...
+def encrypt_files(
+    files: Sequence[Tuple[_Path, _Path]],
+    recipients: Sequence[_Recipient],
+    armored: bool = False,
+    *,
+    workers: Optional[int] = None,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    cancel: Optional[CancellationToken] = None,
+) -> list[BatchResult]: ...
+def decrypt_files(
+    files: Sequence[Tuple[_Path, _Path]],
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    workers: Optional[int] = None,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    cancel: Optional[CancellationToken] = None,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> list[BatchResult]: ...
+def encrypt_tree(
+    source: _Path,
+    destination: _Path,
+    recipients: Sequence[_Recipient],
+    armored: bool = False,
+    *,
+    include: Optional[Sequence[str]] = None,
+    exclude: Optional[Sequence[str]] = None,
+    workers: Optional[int] = None,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    cancel: Optional[CancellationToken] = None,
+) -> list[BatchResult]: ...
+def decrypt_tree(
+    source: _Path,
+    destination: _Path,
+    identities: Sequence[_Identity],
+    format: _Format = "auto",
+    *,
+    include: Optional[Sequence[str]] = None,
+    exclude: Optional[Sequence[str]] = None,
+    workers: Optional[int] = None,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    cancel: Optional[CancellationToken] = None,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> list[BatchResult]: ...
+def rewrap(
+    infile: _FileArg,
+    outfile: _FileArg,
+    identities: Sequence[_Identity],
+    add_recipients: Sequence[_Recipient] = ...,
+    remove: Union[Literal["all"], Sequence[_Identity], None] = None,
+    *,
+    format: _Format = "auto",
+    passphrase: Union[str, bytearray, None] = None,
+    mode: int = 0o600,
+    overwrite: bool = True,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+    max_work_factor: Optional[int] = None,
+) -> None: ...
+def reencrypt_file(
+    infile: _FileArg,
+    outfile: _FileArg,
+    identities: Sequence[_Identity],
+    recipients: Sequence[_Recipient],
+    armored: bool = False,
+    *,
+    format: _Format = "auto",
+    mode: int = 0o600,
+    overwrite: bool = True,
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+) -> None: ...
+def reencrypt_io(
+    in_io: BufferedIOBase,
+    out_io: BufferedIOBase,
+    identities: Sequence[_Identity],
+    recipients: Sequence[_Recipient],
+    armored: bool = False,
+    *,
+    format: _Format = "auto",
+    progress: Optional[_Progress] = None,
+    progress_interval: int = 1024 * 1024,
+    cancel: Optional[CancellationToken] = None,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
 ) -> None: ...
+def main(argv: Optional[Sequence[str]] = None) -> int: ...
@@ -1,2 +1,43 @@
-def encrypt(plaintext: bytes, passphrase: str, armored: bool = False) -> bytes: ...
-def decrypt(ciphertext: bytes, passphrase: str, armored: bool = False) -> bytes: ...
+import sys
+from typing import Callable, Literal, Optional, Protocol, Union
+
+if sys.version_info >= (3, 12):
+    from collections.abc import Buffer
+else:
+    from typing_extensions import Buffer
+
+from pyrage import SecretBuffer
+
+class _PassphraseCallbacks(Protocol):
+    def request_passphrase(self, description: str) -> Optional[Union[str, bytearray]]: ...
+
+_Prompt = Union[
+    Callable[[str], Optional[Union[str, bytearray]]], _PassphraseCallbacks
+]
+
+def encrypt(
+    plaintext: Buffer, passphrase: Union[str, bytearray], armored: bool = False
+) -> bytes: ...
+def decrypt(
+    ciphertext: Union[Buffer, str],
+    passphrase: Union[str, bytearray, _Prompt],
+    format: Literal["auto", "binary", "armored"] = "auto",
+    *,
+    output_type: Literal["bytes", "bytearray", "secret"] = "bytes",
+    max_attempts: int = 3,
+    max_plaintext_size: Optional[int] = None,
+    max_header_size: Optional[int] = None,
+    max_stanzas: Optional[int] = None,
+    max_work_factor: Optional[int] = None,
+) -> Union[bytes, bytearray, SecretBuffer]: ...
+
+class Pinentry:
+    def __init__(
+        self, program: Optional[str] = None, title: Optional[str] = None
+    ) -> None: ...
+    def display_message(self, message: str) -> None: ...
+    def confirm(
+        self, message: str, yes_string: str, no_string: Optional[str] = None
+    ) -> Optional[bool]: ...
+    def request_public_string(self, description: str) -> Optional[str]: ...
+    def request_passphrase(self, description: str) -> Optional[str]: ...
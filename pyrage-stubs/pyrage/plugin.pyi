@@ -1,5 +1,6 @@
 from __future__ import annotations
-from typing import Sequence, Self, Optional, Protocol
+import os
+from typing import List, Mapping, Sequence, Self, Optional, Protocol, Union
 
 
 class Callbacks(Protocol):
@@ -26,7 +27,7 @@ class Recipient:
 
 
 class RecipientPluginV1:
-    def __new__(cls, plugin_name: str, recipients: Sequence[Recipient], identities: Sequence[Identity], callbacks: Callbacks) -> Self:
+    def __new__(cls, plugin_name: str, recipients: Sequence[Recipient], identities: Sequence[Identity], callbacks: Callbacks, *, path: Optional[Union[str, bytes, os.PathLike[str], os.PathLike[bytes]]] = None, env: Optional[Mapping[str, str]] = None, timeout: Optional[float] = None) -> Self:
         ...
 
 
@@ -44,5 +45,32 @@ class Identity:
 
 
 class IdentityPluginV1:
-    def __new__(cls, plugin_name: str, identities: Sequence[Identity], callbacks: Callbacks) -> Self:
+    def __new__(cls, plugin_name: str, identities: Sequence[Identity], callbacks: Callbacks, *, path: Optional[Union[str, bytes, os.PathLike[str], os.PathLike[bytes]]] = None, env: Optional[Mapping[str, str]] = None, timeout: Optional[float] = None) -> Self:
         ...
+
+
+class Stanza:
+    tag: str
+    args: List[str]
+    body: bytes
+
+    def __new__(cls, tag: str, args: Sequence[str], body: bytes) -> Self:
+        ...
+
+
+class ClientCallbacks(Callbacks):
+    ...
+
+
+class RecipientImpl(Protocol):
+    def wrap_file_key(self, file_key: bytes, recipients: List[Recipient], identities: List[Identity], callbacks: ClientCallbacks) -> Sequence[Stanza]:
+        ...
+
+
+class IdentityImpl(Protocol):
+    def unwrap_file_key(self, stanzas: List[Stanza], identities: List[Identity], callbacks: ClientCallbacks) -> Optional[bytes]:
+        ...
+
+
+def serve(recipient_impl: Optional[RecipientImpl] = None, identity_impl: Optional[IdentityImpl] = None, state_machine: Optional[str] = None) -> None:
+    ...
@@ -1,13 +1,21 @@
 from __future__ import annotations
 
+from pyrage import x25519
+
 
 class Identity:
     @classmethod
     def from_buffer(cls, buf: bytes) -> Identity:
         ...
 
+    def to_x25519(self) -> x25519.Identity:
+        ...
+
 
 class Recipient:
     @classmethod
     def from_str(cls, v: str) -> Recipient:
         ...
+
+    def to_x25519(self) -> x25519.Recipient:
+        ...
@@ -13,6 +13,9 @@ class Identity:
     def to_public(self) -> Recipient:
         ...
 
+    def reveal_secret(self) -> str:
+        ...
+
 
 class Recipient:
     @classmethod
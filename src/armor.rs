@@ -0,0 +1,116 @@
+use std::io::{self, Cursor, Read, Write};
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use pyo3::{exceptions::PyValueError, prelude::*, pybacked::PyBackedStr, types::PyBytes};
+
+use crate::DecryptError;
+
+// Armored age files always begin with this exact line; `age` doesn't allow
+// any leading whitespace.
+const ARMORED_BEGIN_MARKER: &[u8] = b"-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// The format that the decryption APIs accept, selected by their `format`
+/// argument.
+#[derive(Clone, Copy)]
+pub(crate) enum InputFormat {
+    Auto,
+    Binary,
+    Armored,
+}
+
+impl<'source> FromPyObject<'source> for InputFormat {
+    fn extract_bound(ob: &Bound<'source, PyAny>) -> PyResult<Self> {
+        match &*ob.extract::<PyBackedStr>()? {
+            "auto" => Ok(Self::Auto),
+            "binary" => Ok(Self::Binary),
+            "armored" => Ok(Self::Armored),
+            other => Err(PyValueError::new_err(format!(
+                "invalid format: {other:?} (expected 'auto', 'binary', or 'armored')"
+            ))),
+        }
+    }
+}
+
+/// A reader whose first bytes have been read ahead, as many as it takes to
+/// tell whether it's armored.
+pub(crate) type Peeked<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+/// Reads ahead the start of `reader`, however short its reads are, so that
+/// it's seen in full by `is_armored`.
+fn peek<R: Read>(mut reader: R) -> io::Result<Peeked<R>> {
+    let mut prefix = Vec::with_capacity(ARMORED_BEGIN_MARKER.len());
+    (&mut reader)
+        .take(ARMORED_BEGIN_MARKER.len() as u64)
+        .read_to_end(&mut prefix)?;
+
+    Ok(Cursor::new(prefix).chain(reader))
+}
+
+/// The bytes that `peek` read ahead.
+pub(crate) fn peeked<R>(reader: &Peeked<R>) -> &[u8] {
+    reader.get_ref().0.get_ref()
+}
+
+impl InputFormat {
+    /// Checks the beginning of an input against this format.
+    fn check(self, prefix: &[u8]) -> PyResult<()> {
+        match (self, prefix.starts_with(ARMORED_BEGIN_MARKER)) {
+            (Self::Binary, true) => Err(DecryptError::new_err(
+                "expected a binary age file, but the input is armored",
+            )),
+            (Self::Armored, false) => Err(DecryptError::new_err(
+                "expected an armored age file, but the input is not armored",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads ahead the start of `reader` and checks it against this format,
+    /// returning a reader over all of the input.
+    pub(crate) fn check_reader<R: Read>(self, reader: R) -> PyResult<Peeked<R>> {
+        let reader = peek(reader)?;
+        self.check(peeked(&reader))?;
+
+        Ok(reader)
+    }
+
+    /// Narrows this format for a text input, which can only be armored.
+    pub(crate) fn for_text(self) -> PyResult<Self> {
+        match self {
+            Self::Binary => Err(DecryptError::new_err(
+                "expected a binary age file, but the input is text",
+            )),
+            _ => Ok(Self::Armored),
+        }
+    }
+}
+
+#[pyfunction]
+pub(crate) fn is_armored(data: &[u8]) -> bool {
+    data.starts_with(ARMORED_BEGIN_MARKER)
+}
+
+#[pyfunction]
+pub(crate) fn armor<'p>(py: Python<'p>, data: &[u8]) -> PyResult<Bound<'p, PyBytes>> {
+    let mut armored = vec![];
+
+    let mut writer = ArmoredWriter::wrap_output(&mut armored, Format::AsciiArmor)?;
+    writer.write_all(data)?;
+    writer.finish()?;
+
+    Ok(PyBytes::new(py, &armored))
+}
+
+#[pyfunction]
+pub(crate) fn dearmor<'p>(py: Python<'p>, data: &[u8]) -> PyResult<Bound<'p, PyBytes>> {
+    if !is_armored(data) {
+        return Err(PyValueError::new_err("input is not armored"));
+    }
+
+    let mut dearmored = vec![];
+    ArmoredReader::new(data)
+        .read_to_end(&mut dearmored)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+    Ok(PyBytes::new(py, &dearmored))
+}
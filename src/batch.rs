@@ -0,0 +1,540 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    Encryptor, Identity, Recipient,
+};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use pyo3::{
+    exceptions::{PyBaseException, PyValueError},
+    prelude::*,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    armor::InputFormat,
+    copy::CancellationToken,
+    decryptor_error, encryptor_error,
+    file::{AtomicFile, FsPath},
+    info::Tracker,
+    keys::{Identities, Recipients},
+    limits::Limits,
+    CancelledError, EncryptError, PyrageRecipient,
+};
+
+// How often the calling thread wakes up to check for signals while the
+// workers run.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A file for `run` to process, or to report as skipped.
+struct Job {
+    source: PathBuf,
+    destination: PathBuf,
+    skipped: Option<&'static str>,
+}
+
+impl Job {
+    fn new(source: PathBuf, destination: PathBuf) -> Self {
+        Self {
+            source,
+            destination,
+            skipped: None,
+        }
+    }
+}
+
+/// The outcome of a single file in a batch; `error` is `None` on success.
+/// Entries of a tree that weren't processed, like symlinks, are reported
+/// with the reason in `skipped`.
+#[pyclass(module = "pyrage", frozen)]
+pub(crate) struct BatchResult {
+    source: PathBuf,
+    destination: PathBuf,
+    error: Option<PyErr>,
+    skipped: Option<&'static str>,
+}
+
+#[pymethods]
+impl BatchResult {
+    #[getter]
+    fn source(&self) -> &Path {
+        &self.source
+    }
+
+    #[getter]
+    fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    #[getter]
+    fn error<'p>(&self, py: Python<'p>) -> Option<Bound<'p, PyBaseException>> {
+        self.error.as_ref().map(|e| e.value(py).clone())
+    }
+
+    #[getter]
+    fn skipped(&self) -> Option<&str> {
+        self.skipped
+    }
+
+    #[getter]
+    fn ok(&self) -> bool {
+        self.error.is_none() && self.skipped.is_none()
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let error = match self.error(py) {
+            Some(error) => error.repr()?.to_string(),
+            None => "None".into(),
+        };
+        let skipped = match self.skipped {
+            Some(reason) => format!("{reason:?}"),
+            None => "None".into(),
+        };
+
+        Ok(format!(
+            "BatchResult(source={:?}, destination={:?}, error={error}, skipped={skipped})",
+            self.source, self.destination
+        ))
+    }
+}
+
+/// Runs `process` over each job's source and destination on a pool of
+/// `workers` threads, with the GIL released. Skipped jobs are only reported.
+///
+/// The calling thread keeps checking for signals, and a signal or a cancelled
+/// token stops the workers from picking up new files. Files that are already
+/// in progress are allowed to finish, so no partial outputs are left behind.
+fn run<F>(
+    py: Python<'_>,
+    jobs: Vec<Job>,
+    workers: Option<usize>,
+    cancel: Option<&CancellationToken>,
+    process: F,
+) -> PyResult<Vec<BatchResult>>
+where
+    F: Fn(&Path, &Path) -> PyResult<()> + Sync,
+{
+    let workers = match workers {
+        Some(0) => return Err(PyValueError::new_err("workers must be at least 1")),
+        Some(workers) => workers,
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    }
+    .min(jobs.len().max(1));
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let stopped = || stop.load(Ordering::Relaxed) || cancel.is_some_and(|t| t.cancelled());
+
+    let mut errors = (0..jobs.len()).map(|_| None).collect::<Vec<_>>();
+    let mut interrupted = None;
+
+    thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut errors = vec![];
+                    while !stopped() {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(job) = jobs.get(i) else {
+                            break;
+                        };
+                        if job.skipped.is_some() {
+                            continue;
+                        }
+                        if let Err(e) = process(&job.source, &job.destination) {
+                            errors.push((i, e));
+                        }
+                    }
+                    errors
+                })
+            })
+            .collect::<Vec<_>>();
+
+        while !handles.iter().all(|h| h.is_finished()) {
+            py.allow_threads(|| thread::sleep(POLL_INTERVAL));
+            if interrupted.is_none() {
+                if let Err(e) = py.check_signals() {
+                    stop.store(true, Ordering::Relaxed);
+                    interrupted = Some(e);
+                }
+            }
+        }
+
+        for handle in handles {
+            // Jobs don't panic on their own; if one did, re-raise it here.
+            let worker_errors = handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            for (i, e) in worker_errors {
+                errors[i] = Some(e);
+            }
+        }
+    });
+
+    if let Some(e) = interrupted {
+        return Err(e);
+    }
+    if cancel.is_some_and(|t| t.cancelled()) {
+        return Err(CancelledError::new_err("operation cancelled"));
+    }
+
+    Ok(jobs
+        .into_iter()
+        .zip(errors)
+        .map(|(job, error)| BatchResult {
+            source: job.source,
+            destination: job.destination,
+            error,
+            skipped: job.skipped,
+        })
+        .collect())
+}
+
+fn encrypt_one(
+    source: &Path,
+    destination: &Path,
+    recipients: &[Box<dyn PyrageRecipient>],
+    armored: bool,
+    mode: u32,
+    overwrite: bool,
+) -> PyResult<()> {
+    let mut reader = BufReader::new(File::open(source)?);
+    let mut output = BufWriter::new(AtomicFile::create(destination, mode, overwrite)?);
+
+    let encryptor =
+        Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as &dyn Recipient))
+            .map_err(encryptor_error)?;
+
+    let format = match armored {
+        true => Format::AsciiArmor,
+        false => Format::Binary,
+    };
+    let mut writer = encryptor
+        .wrap_output(ArmoredWriter::wrap_output(&mut output, format)?)
+        .map_err(|e| EncryptError::new_err(e.to_string()))?;
+
+    std::io::copy(&mut reader, &mut writer).map_err(|e| EncryptError::new_err(e.to_string()))?;
+
+    writer
+        .finish()
+        .map_err(|e| EncryptError::new_err(e.to_string()))?
+        .finish()
+        .map_err(|e| EncryptError::new_err(e.to_string()))?;
+
+    output.into_inner().map_err(|e| e.into_error())?.persist()?;
+
+    Ok(())
+}
+
+fn decrypt_one(
+    source: &Path,
+    destination: &Path,
+    identities: &Identities,
+    format: InputFormat,
+    mode: u32,
+    overwrite: bool,
+    limits: Limits,
+) -> PyResult<()> {
+    let tracker = Tracker::new(identities);
+
+    let reader = format.check_reader(BufReader::new(File::open(source)?))?;
+
+    let mut writer = BufWriter::new(AtomicFile::create(destination, mode, overwrite)?);
+
+    let decryptor = age::Decryptor::new_buffered(limits.header(ArmoredReader::new(reader)))
+        .map_err(decryptor_error)?;
+
+    let mut reader = decryptor
+        .decrypt(std::iter::once(&tracker as &dyn Identity))
+        .map_err(|e| tracker.error(e))?;
+
+    std::io::copy(&mut reader, &mut limits.plaintext(&mut writer))?;
+
+    writer.into_inner().map_err(|e| e.into_error())?.persist()?;
+
+    Ok(())
+}
+
+fn globset(patterns: Option<Vec<String>>) -> PyResult<Option<GlobSet>> {
+    let Some(patterns) = patterns else {
+        return Ok(None);
+    };
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(&pattern).map_err(|e| PyValueError::new_err(e.to_string()))?);
+    }
+
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// `path` made absolute, with symlinks resolved, even if the path (like a
+/// destination that's yet to be created) doesn't exist yet: its deepest
+/// existing ancestor is resolved, and the rest joined on.
+fn resolve(path: &Path) -> std::io::Result<PathBuf> {
+    let path = std::path::absolute(path)?;
+    let mut existing = path.as_path();
+    let mut rest = vec![];
+    let mut resolved = loop {
+        match existing.canonicalize() {
+            Ok(resolved) => break resolved,
+            Err(e) => {
+                rest.extend(existing.components().next_back());
+                existing = existing.parent().ok_or(e)?;
+            }
+        }
+    };
+
+    for component in rest.into_iter().rev() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => (),
+        }
+    }
+    Ok(resolved)
+}
+
+/// Pairs each file under `source` with its mirror under `destination`,
+/// creating the destination's directories along the way.
+///
+/// `include` and `exclude` are matched against paths relative to `source`;
+/// excluded directories aren't descended into. `rename` maps each relative
+/// file path to its destination, or to the reason it's skipped. Skipped
+/// files, and anything else that would be mirrored but isn't a regular file,
+/// are returned as skipped.
+///
+/// A destination inside `source` is rejected, since the walk would pick up
+/// its own output.
+fn mirror(
+    source: &Path,
+    destination: &Path,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    rename: impl Fn(&Path) -> Result<PathBuf, &'static str>,
+) -> PyResult<Vec<Job>> {
+    let include = globset(include)?;
+    let exclude = globset(exclude)?;
+
+    if resolve(destination)?.starts_with(resolve(source)?) {
+        return Err(PyValueError::new_err(format!(
+            "destination {destination:?} is inside the source directory {source:?}"
+        )));
+    }
+
+    let relative = |path: &Path| path.strip_prefix(source).unwrap_or(path).to_owned();
+
+    let walk = WalkDir::new(source)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || !exclude
+                    .as_ref()
+                    .is_some_and(|exclude| exclude.is_match(relative(entry.path())))
+        });
+
+    let mut jobs = vec![];
+    for entry in walk {
+        let entry = entry.map_err(std::io::Error::from)?;
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            continue;
+        }
+
+        let path = relative(entry.path());
+        if include
+            .as_ref()
+            .is_some_and(|include| !include.is_match(&path))
+        {
+            continue;
+        }
+
+        let renamed = rename(&path);
+        let mut job = Job::new(
+            entry.into_path(),
+            destination.join(renamed.as_deref().unwrap_or(&path)),
+        );
+        if file_type.is_symlink() {
+            job.skipped = Some("symlinks aren't followed");
+        } else if !file_type.is_file() {
+            job.skipped = Some("not a regular file");
+        } else if let Err(reason) = renamed {
+            job.skipped = Some(reason);
+        } else if let Some(parent) = job.destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        jobs.push(job);
+    }
+
+    Ok(jobs)
+}
+
+fn with_age_extension(path: &Path) -> Result<PathBuf, &'static str> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".age");
+    Ok(name.into())
+}
+
+fn without_age_extension(path: &Path) -> Result<PathBuf, &'static str> {
+    match path.extension() {
+        Some(ext) if ext == "age" => Ok(path.with_extension("")),
+        _ => Err("no .age extension"),
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (files, recipients, armored=false, *, workers=None, mode=0o600, overwrite=true, cancel=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encrypt_files(
+    py: Python<'_>,
+    files: Vec<(FsPath, FsPath)>,
+    recipients: Recipients,
+    armored: bool,
+    workers: Option<usize>,
+    mode: u32,
+    overwrite: bool,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+) -> PyResult<Vec<BatchResult>> {
+    let jobs = files.into_iter().map(|(s, d)| Job::new(s.0, d.0)).collect();
+
+    run(
+        py,
+        jobs,
+        workers,
+        cancel.as_deref(),
+        |source, destination| {
+            encrypt_one(source, destination, &recipients, armored, mode, overwrite)
+        },
+    )
+}
+
+#[pyfunction]
+#[pyo3(signature = (files, identities, format=InputFormat::Auto, *, workers=None, mode=0o600, overwrite=true, cancel=None, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decrypt_files(
+    py: Python<'_>,
+    files: Vec<(FsPath, FsPath)>,
+    identities: Identities,
+    format: InputFormat,
+    workers: Option<usize>,
+    mode: u32,
+    overwrite: bool,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<Vec<BatchResult>> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+    let jobs = files.into_iter().map(|(s, d)| Job::new(s.0, d.0)).collect();
+
+    run(
+        py,
+        jobs,
+        workers,
+        cancel.as_deref(),
+        |source, destination| {
+            decrypt_one(
+                source,
+                destination,
+                &identities,
+                format,
+                mode,
+                overwrite,
+                limits,
+            )
+        },
+    )
+}
+
+#[pyfunction]
+#[pyo3(signature = (source, destination, recipients, armored=false, *, include=None, exclude=None, workers=None, mode=0o600, overwrite=true, cancel=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encrypt_tree(
+    py: Python<'_>,
+    source: FsPath,
+    destination: FsPath,
+    recipients: Recipients,
+    armored: bool,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    workers: Option<usize>,
+    mode: u32,
+    overwrite: bool,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+) -> PyResult<Vec<BatchResult>> {
+    let jobs = mirror(
+        &source.0,
+        &destination.0,
+        include,
+        exclude,
+        with_age_extension,
+    )?;
+
+    run(
+        py,
+        jobs,
+        workers,
+        cancel.as_deref(),
+        |source, destination| {
+            encrypt_one(source, destination, &recipients, armored, mode, overwrite)
+        },
+    )
+}
+
+#[pyfunction]
+#[pyo3(signature = (source, destination, identities, format=InputFormat::Auto, *, include=None, exclude=None, workers=None, mode=0o600, overwrite=true, cancel=None, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decrypt_tree(
+    py: Python<'_>,
+    source: FsPath,
+    destination: FsPath,
+    identities: Identities,
+    format: InputFormat,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    workers: Option<usize>,
+    mode: u32,
+    overwrite: bool,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<Vec<BatchResult>> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+    let jobs = mirror(
+        &source.0,
+        &destination.0,
+        include,
+        exclude,
+        without_age_extension,
+    )?;
+
+    run(
+        py,
+        jobs,
+        workers,
+        cancel.as_deref(),
+        |source, destination| {
+            decrypt_one(
+                source,
+                destination,
+                &identities,
+                format,
+                mode,
+                overwrite,
+                limits,
+            )
+        },
+    )
+}
@@ -0,0 +1,235 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    pybacked::PyBackedStr,
+    types::{PyByteArray, PyBytes, PyMemoryView, PySlice, PyString},
+};
+use zeroize::Zeroizing;
+
+use crate::{armor::InputFormat, stream::CHUNK_SIZE};
+
+/// A flat, unsigned byte view of any object that exports a buffer.
+///
+/// pyo3 can't borrow arbitrary buffers under the stable ABI, so these are
+/// read and written through a `memoryview`, a chunk at a time, rather than
+/// copied whole.
+pub(crate) struct View<'py> {
+    view: Bound<'py, PyAny>,
+    len: usize,
+}
+
+impl<'py> View<'py> {
+    fn new(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        // Casting to "B" flattens typed and multi-dimensional buffers, like
+        // NumPy arrays, into their raw bytes, and rejects non-contiguous ones.
+        let view = PyMemoryView::from(ob)?.call_method1("cast", ("B",))?;
+        let len = view.len()?;
+
+        Ok(Self { view, len })
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Bound<'py, PySlice> {
+        PySlice::new(self.view.py(), start as isize, end as isize, 1)
+    }
+
+    /// Copies `data` into the buffer at `start`. It has to pass through a
+    /// Python object on the way, and a `bytearray`, unlike `bytes`, can be
+    /// overwritten with zeros (in place) once it's been copied from.
+    fn write(&self, start: usize, data: &[u8]) -> PyResult<()> {
+        let py = self.view.py();
+        let scratch = PyByteArray::new(py, data);
+        let result = self
+            .view
+            .set_item(self.slice(start, start + data.len()), &scratch);
+
+        let zeros = PyBytes::new_with(py, data.len(), |_| Ok(()))?;
+        scratch.set_item(PySlice::new(py, 0, data.len() as isize, 1), zeros)?;
+        result
+    }
+}
+
+// This holds its own reference to the `memoryview`, and takes the GIL for
+// each read, so that it can be read with the GIL released, like a file.
+struct ViewReader {
+    view: Py<PyAny>,
+    len: usize,
+    pos: usize,
+}
+
+impl Read for ViewReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let end = self.len.min(self.pos + buf.len());
+        let n = Python::with_gil(|py| -> PyResult<usize> {
+            let slice = PySlice::new(py, self.pos as isize, end as isize, 1);
+            let chunk = self
+                .view
+                .bind(py)
+                .get_item(slice)?
+                .call_method0("tobytes")?
+                .downcast_into::<PyBytes>()?;
+            let chunk = chunk.as_bytes();
+
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        })?;
+
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A bytes-like input: `bytes`, which is borrowed directly, or any other
+/// contiguous buffer, such as a `bytearray`, `memoryview`, `mmap` or NumPy
+/// array.
+pub(crate) enum Buffer<'py> {
+    Bytes(Bound<'py, PyBytes>),
+    View(View<'py>),
+}
+
+impl<'py> FromPyObject<'py> for Buffer<'py> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        match ob.downcast::<PyBytes>() {
+            Ok(bytes) => Ok(Self::Bytes(bytes.clone())),
+            Err(_) => Ok(Self::View(View::new(ob)?)),
+        }
+    }
+}
+
+impl Buffer<'_> {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Bytes(bytes) => bytes.as_bytes().len(),
+            Self::View(view) => view.len,
+        }
+    }
+
+    pub(crate) fn reader(&self) -> Box<dyn BufRead + Send + '_> {
+        match self {
+            Self::Bytes(bytes) => Box::new(bytes.as_bytes()),
+            Self::View(view) => Box::new(BufReader::with_capacity(
+                CHUNK_SIZE,
+                ViewReader {
+                    view: view.view.clone().unbind(),
+                    len: view.len,
+                    pos: 0,
+                },
+            )),
+        }
+    }
+}
+
+/// A writable contiguous buffer, such as a `bytearray` or a writable `mmap`,
+/// that output is written into from the start.
+pub(crate) struct BufferMut<'py> {
+    view: View<'py>,
+    written: usize,
+}
+
+impl<'py> FromPyObject<'py> for BufferMut<'py> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let view = View::new(ob)?;
+        if view.view.getattr("readonly")?.is_truthy()? {
+            return Err(PyTypeError::new_err("expected a writable buffer"));
+        }
+
+        Ok(Self { view, written: 0 })
+    }
+}
+
+impl BufferMut<'_> {
+    /// Copies all of `reader` into the buffer, and returns the number of bytes
+    /// written.
+    ///
+    /// If `reader` fails or doesn't fit, whatever was already written is
+    /// overwritten with zeros before the error is returned, so that the
+    /// buffer never holds a partial output.
+    pub(crate) fn copy_from(
+        &mut self,
+        mut reader: impl Read,
+        io_error: impl Fn(io::Error) -> PyErr,
+    ) -> PyResult<usize> {
+        let mut chunk = Zeroizing::new(vec![0; CHUNK_SIZE]);
+        let result = loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => break Ok(self.written),
+                Ok(n) => {
+                    if let Err(e) = self.write(&chunk[..n]) {
+                        break Err(e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(io_error(e)),
+            }
+        };
+
+        if result.is_err() {
+            self.wipe()?;
+        }
+        result
+    }
+
+    fn write(&mut self, data: &[u8]) -> PyResult<()> {
+        if data.len() > self.view.len - self.written {
+            return Err(PyValueError::new_err(format!(
+                "output buffer is too small ({} bytes)",
+                self.view.len
+            )));
+        }
+
+        self.view.write(self.written, data)?;
+        self.written += data.len();
+        Ok(())
+    }
+
+    fn wipe(&mut self) -> PyResult<()> {
+        let zeros = vec![0; CHUNK_SIZE];
+        for start in (0..self.written).step_by(CHUNK_SIZE) {
+            let end = self.written.min(start + CHUNK_SIZE);
+            self.view.write(start, &zeros[..end - start])?;
+        }
+        self.written = 0;
+
+        Ok(())
+    }
+}
+
+/// A decryption input: any `Buffer`, or a `str` holding an armored age file.
+pub(crate) enum Ciphertext<'py> {
+    Buffer(Buffer<'py>),
+    Text(PyBackedStr),
+}
+
+impl<'py> FromPyObject<'py> for Ciphertext<'py> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        match ob.downcast::<PyString>() {
+            Ok(text) => Ok(Self::Text(text.extract()?)),
+            Err(_) => Ok(Self::Buffer(ob.extract()?)),
+        }
+    }
+}
+
+impl Ciphertext<'_> {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Buffer(buffer) => buffer.len(),
+            Self::Text(text) => text.len(),
+        }
+    }
+
+    /// Checks the beginning of the input against `format`, and returns a
+    /// reader over all of it.
+    pub(crate) fn reader(&self, format: InputFormat) -> PyResult<Box<dyn BufRead + Send + '_>> {
+        let (format, reader) = match self {
+            Self::Buffer(buffer) => (format, buffer.reader()),
+            // Pasted text often carries surrounding whitespace, which age's
+            // armor parser would reject.
+            Self::Text(text) => (
+                format.for_text()?,
+                Box::new(text.trim().as_bytes()) as Box<dyn BufRead + Send>,
+            ),
+        };
+        Ok(Box::new(format.check_reader(reader)?))
+    }
+}
@@ -0,0 +1,561 @@
+//! The `pyrage` command-line interface, which follows `rage`'s flags and
+//! identity and recipients file formats.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use age::{
+    armor::ArmoredReader,
+    plugin, scrypt,
+    secrecy::{ExposeSecret, SecretString},
+    ssh, Decryptor, Identity, Recipient,
+};
+use pyo3::{
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+};
+
+use crate::{
+    armor, copy, encrypt_stream,
+    file::FileArg,
+    keys::{IdentityKey, RecipientKey, SSH_KEY_MARKERS},
+    plugin::client,
+    DecryptError, EncryptError, IdentityError, RecipientError,
+};
+
+const USAGE: &str = "\
+Usage: pyrage [-e] [-a] [-p] [-r RECIPIENT]... [-R PATH]... [-i PATH]... [-j PLUGIN]... [-o OUTPUT] [INPUT]
+       pyrage -d [-i PATH]... [-j PLUGIN]... [-o OUTPUT] [INPUT]
+
+Options:
+  -e, --encrypt                 Encrypt the input (the default).
+  -d, --decrypt                 Decrypt the input.
+  -p, --passphrase              Encrypt with a passphrase instead of recipients.
+  -a, --armor                   Encrypt to a PEM encoded format.
+  -r, --recipient RECIPIENT     Encrypt to RECIPIENT. May be repeated.
+  -R, --recipients-file PATH    Encrypt to the recipients listed at PATH. May be repeated.
+  -i, --identity PATH           Use the identity file at PATH. May be repeated.
+  -j PLUGIN                     Use age-plugin-PLUGIN in its default mode as an identity.
+  -o, --output OUTPUT           Write the result to the file at path OUTPUT.
+  -h, --help                    Print this help message and exit.
+  -V, --version                 Print the version and exit.
+
+INPUT defaults to standard input, and OUTPUT to standard output. Passphrase-
+encrypted files are detected when decrypting, and prompt for the passphrase.";
+
+#[derive(Default)]
+struct Options {
+    encrypt: bool,
+    decrypt: bool,
+    passphrase: bool,
+    armor: bool,
+    help: bool,
+    version: bool,
+    recipients: Vec<String>,
+    recipients_files: Vec<PathBuf>,
+    identities: Vec<PathBuf>,
+    plugins: Vec<String>,
+    output: Option<OsString>,
+    input: Option<OsString>,
+}
+
+fn usage_error(message: impl Into<String>) -> PyErr {
+    PyValueError::new_err(message.into())
+}
+
+fn utf8(flag: &str, value: OsString) -> PyResult<String> {
+    value
+        .into_string()
+        .map_err(|_| usage_error(format!("{flag} must be valid UTF-8")))
+}
+
+impl Options {
+    fn parse(args: Vec<OsString>) -> PyResult<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        let mut positional_only = false;
+
+        while let Some(arg) = args.next() {
+            let text = arg.to_string_lossy().into_owned();
+            if positional_only || text == "-" || !text.starts_with('-') {
+                if options.input.replace(arg).is_some() {
+                    return Err(usage_error("only one input file may be given"));
+                }
+                continue;
+            }
+
+            // `--flag=value`, `--flag value`, `-fvalue`, `-f value`, and
+            // bundles of short flags like `-da`.
+            let flags = match text.strip_prefix("--") {
+                Some("") => {
+                    positional_only = true;
+                    continue;
+                }
+                Some(long) => match long.split_once('=') {
+                    Some((flag, value)) => vec![(format!("--{flag}"), Some(value.to_owned()))],
+                    None => vec![(text.clone(), None)],
+                },
+                None => {
+                    let mut flags = vec![];
+                    let mut rest = &text[1..];
+                    while let Some(c) = rest.chars().next() {
+                        rest = &rest[c.len_utf8()..];
+                        if "rRijo".contains(c) && !rest.is_empty() {
+                            flags.push((format!("-{c}"), Some(rest.to_owned())));
+                            break;
+                        }
+                        flags.push((format!("-{c}"), None));
+                    }
+                    flags
+                }
+            };
+
+            for (flag, inline) in flags {
+                let takes_value = matches!(
+                    flag.as_str(),
+                    "-r" | "--recipient"
+                        | "-R"
+                        | "--recipients-file"
+                        | "-i"
+                        | "--identity"
+                        | "-j"
+                        | "-o"
+                        | "--output"
+                );
+                let value = match (takes_value, inline) {
+                    (true, Some(value)) => Some(OsString::from(value)),
+                    (true, None) => Some(
+                        args.next()
+                            .ok_or_else(|| usage_error(format!("{flag} requires a value")))?,
+                    ),
+                    (false, Some(_)) => {
+                        return Err(usage_error(format!("{flag} doesn't take a value")))
+                    }
+                    (false, None) => None,
+                };
+
+                match (flag.as_str(), value) {
+                    ("-e" | "--encrypt", _) => options.encrypt = true,
+                    ("-d" | "--decrypt", _) => options.decrypt = true,
+                    ("-p" | "--passphrase", _) => options.passphrase = true,
+                    ("-a" | "--armor", _) => options.armor = true,
+                    ("-h" | "--help", _) => options.help = true,
+                    ("-V" | "--version", _) => options.version = true,
+                    ("-r" | "--recipient", Some(v)) => options.recipients.push(utf8(&flag, v)?),
+                    ("-R" | "--recipients-file", Some(v)) => {
+                        options.recipients_files.push(v.into())
+                    }
+                    ("-i" | "--identity", Some(v)) => options.identities.push(v.into()),
+                    ("-j", Some(v)) => options.plugins.push(utf8(&flag, v)?),
+                    ("-o" | "--output", Some(v)) => options.output = Some(v),
+                    _ => return Err(usage_error(format!("unknown option: {flag}"))),
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+// Standard input and output are used through their descriptors, which
+// `FileArg` leaves open.
+fn file_arg(arg: Option<OsString>, fd: i32) -> FileArg {
+    match arg {
+        Some(path) if path != "-" => FileArg::Path(path.into()),
+        _ => FileArg::Fd(fd),
+    }
+}
+
+fn read_passphrase(py: Python<'_>, prompt: &str) -> PyResult<SecretString> {
+    let passphrase = py
+        .import(pyo3::intern!(py, "getpass"))?
+        .call_method1(pyo3::intern!(py, "getpass"), (prompt,))?
+        .extract::<String>()?;
+
+    Ok(passphrase.into())
+}
+
+fn read_new_passphrase(py: Python<'_>) -> PyResult<SecretString> {
+    let passphrase = read_passphrase(py, "Enter passphrase: ")?;
+    if passphrase.expose_secret().is_empty() {
+        return Err(EncryptError::new_err("passphrase can't be empty"));
+    }
+    if read_passphrase(py, "Confirm passphrase: ")?.expose_secret() != passphrase.expose_secret() {
+        return Err(EncryptError::new_err("passphrases didn't match"));
+    }
+
+    Ok(passphrase)
+}
+
+// Interactive plugin requests go to the terminal, as they do in `rage`.
+#[derive(Clone)]
+struct TerminalCallbacks;
+
+impl TerminalCallbacks {
+    fn read_line(prompt: &str) -> Option<String> {
+        eprint!("{prompt}");
+
+        let mut line = String::new();
+        let read = match fs::File::open("/dev/tty") {
+            Ok(tty) => BufReader::new(tty).read_line(&mut line),
+            Err(_) => io::stdin().lock().read_line(&mut line),
+        };
+        read.ok().filter(|&n| n > 0)?;
+
+        Some(line.trim_end_matches(['\r', '\n']).to_owned())
+    }
+}
+
+impl age::Callbacks for TerminalCallbacks {
+    fn display_message(&self, message: &str) {
+        eprintln!("{message}");
+    }
+
+    fn confirm(&self, message: &str, yes_string: &str, no_string: Option<&str>) -> Option<bool> {
+        let choices = match no_string {
+            Some(no_string) => format!("{yes_string}/{no_string}"),
+            None => yes_string.to_owned(),
+        };
+        let answer = Self::read_line(&format!("{message} [{choices}]: "))?;
+
+        match answer {
+            a if a == yes_string => Some(true),
+            a if Some(a.as_str()) == no_string => Some(false),
+            _ => None,
+        }
+    }
+
+    fn request_public_string(&self, description: &str) -> Option<String> {
+        Self::read_line(&format!("{description} "))
+    }
+
+    fn request_passphrase(&self, description: &str) -> Option<SecretString> {
+        Python::with_gil(|py| read_passphrase(py, &format!("{description} ")).ok())
+    }
+}
+
+/// Reads an identity file: either an SSH private key, or age identities one
+/// per line. The latter may themselves be passphrase-encrypted with age.
+fn read_identity_file(py: Python<'_>, path: &Path) -> PyResult<Vec<IdentityKey>> {
+    let mut contents = fs::read(path)?;
+
+    if contents.starts_with(b"age-encryption.org/") || armor::is_armored(&contents) {
+        let prompt = format!("Enter passphrase for identity file '{}': ", path.display());
+        let identity = scrypt::Identity::new(read_passphrase(py, &prompt)?);
+
+        let mut decrypted = vec![];
+        Decryptor::new_buffered(ArmoredReader::new(&contents[..]))
+            .and_then(|d| d.decrypt(std::iter::once(&identity as &dyn Identity)))
+            .map_err(|e| DecryptError::new_err(e.to_string()))?
+            .read_to_end(&mut decrypted)?;
+        contents = decrypted;
+    }
+
+    let text = String::from_utf8_lossy(&contents);
+    if SSH_KEY_MARKERS.iter().any(|marker| text.contains(marker)) {
+        let filename = path.display().to_string();
+        return match ssh::Identity::from_buffer(&contents[..], Some(filename))? {
+            ssh::Identity::Unsupported(key) => Err(IdentityError::new_err(format!(
+                "{}: unsupported key: {key:?}",
+                path.display()
+            ))),
+            identity => Ok(vec![IdentityKey::Ssh(identity)]),
+        };
+    }
+
+    let mut entries = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        entries.push(IdentityKey::from_str(line).map_err(|_| {
+            IdentityError::new_err(format!("{}:{}: invalid identity", path.display(), n + 1))
+        })?);
+    }
+
+    if entries.is_empty() {
+        return Err(IdentityError::new_err(format!(
+            "{}: no identities found",
+            path.display()
+        )));
+    }
+
+    Ok(entries)
+}
+
+fn missing_plugin(binary_name: String) -> String {
+    format!("could not find '{binary_name}' in PATH")
+}
+
+/// Plugin recipients and identities, grouped by plugin so that each plugin
+/// binary runs once.
+#[derive(Default)]
+struct Plugins(BTreeMap<String, (Vec<plugin::Recipient>, Vec<plugin::Identity>)>);
+
+impl Plugins {
+    fn add_recipient(&mut self, recipient: plugin::Recipient) {
+        let name = recipient.plugin().to_owned();
+        self.0.entry(name).or_default().0.push(recipient);
+    }
+
+    fn add_identity(&mut self, identity: plugin::Identity) {
+        let name = identity.plugin().to_owned();
+        self.0.entry(name).or_default().1.push(identity);
+    }
+
+    fn add_default(&mut self, name: &str) -> PyResult<()> {
+        // `default_for_plugin` panics on invalid names.
+        if !client::valid_plugin_name(name) {
+            return Err(usage_error(format!("invalid plugin name: {name}")));
+        }
+
+        self.add_identity(plugin::Identity::default_for_plugin(name));
+        Ok(())
+    }
+}
+
+fn parse_recipient(
+    recipient: &str,
+    recipients: &mut Vec<Box<dyn Recipient + Send + Sync>>,
+    plugins: &mut Plugins,
+) -> Result<(), ()> {
+    match RecipientKey::from_str(recipient)? {
+        RecipientKey::X25519(r) => recipients.push(Box::new(r)),
+        RecipientKey::Ssh(r) => recipients.push(Box::new(r)),
+        RecipientKey::Plugin(r) => plugins.add_recipient(r),
+    }
+
+    Ok(())
+}
+
+fn recipients(
+    py: Python<'_>,
+    options: &Options,
+) -> PyResult<Vec<Box<dyn Recipient + Send + Sync>>> {
+    let mut recipients: Vec<Box<dyn Recipient + Send + Sync>> = vec![];
+    let mut plugins = Plugins::default();
+
+    for recipient in &options.recipients {
+        parse_recipient(recipient, &mut recipients, &mut plugins)
+            .map_err(|_| RecipientError::new_err(format!("invalid recipient: {recipient}")))?;
+    }
+
+    for path in &options.recipients_files {
+        let contents = fs::read_to_string(path)?;
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            parse_recipient(line, &mut recipients, &mut plugins).map_err(|_| {
+                RecipientError::new_err(format!("{}:{}: invalid recipient", path.display(), n + 1))
+            })?;
+        }
+    }
+
+    for path in &options.identities {
+        for entry in read_identity_file(py, path)? {
+            match entry {
+                IdentityKey::X25519(identity) => recipients.push(Box::new(identity.to_public())),
+                IdentityKey::Plugin(identity) => plugins.add_identity(identity),
+                IdentityKey::Ssh(_) => {
+                    return Err(RecipientError::new_err(format!(
+                        "{}: SSH identities can't be used to encrypt; pass the public key with -r",
+                        path.display()
+                    )))
+                }
+            }
+        }
+    }
+
+    for name in &options.plugins {
+        plugins.add_default(name)?;
+    }
+
+    for (name, (plugin_recipients, plugin_identities)) in plugins.0 {
+        recipients.push(Box::new(client::RecipientPluginV1 {
+            plugin: client::Plugin::new(&name, None, None, None)
+                .map_err(|b| EncryptError::new_err(missing_plugin(b)))?,
+            recipients: plugin_recipients,
+            identities: plugin_identities,
+            callbacks: TerminalCallbacks,
+        }));
+    }
+
+    Ok(recipients)
+}
+
+fn identities(py: Python<'_>, options: &Options) -> PyResult<Vec<Box<dyn Identity>>> {
+    let mut identities: Vec<Box<dyn Identity>> = vec![];
+    let mut plugins = Plugins::default();
+
+    for path in &options.identities {
+        for entry in read_identity_file(py, path)? {
+            match entry {
+                IdentityKey::X25519(identity) => identities.push(Box::new(identity)),
+                IdentityKey::Plugin(identity) => plugins.add_identity(identity),
+                IdentityKey::Ssh(identity) => {
+                    identities.push(Box::new(identity.with_callbacks(TerminalCallbacks)))
+                }
+            }
+        }
+    }
+
+    for name in &options.plugins {
+        plugins.add_default(name)?;
+    }
+
+    for (name, (_, plugin_identities)) in plugins.0 {
+        identities.push(Box::new(client::IdentityPluginV1 {
+            plugin: client::Plugin::new(&name, None, None, None)
+                .map_err(|b| DecryptError::new_err(missing_plugin(b)))?,
+            identities: plugin_identities,
+            callbacks: TerminalCallbacks,
+        }));
+    }
+
+    Ok(identities)
+}
+
+fn encrypt(py: Python<'_>, options: Options) -> PyResult<()> {
+    let recipients = if options.passphrase {
+        if !options.recipients.is_empty()
+            || !options.recipients_files.is_empty()
+            || !options.identities.is_empty()
+            || !options.plugins.is_empty()
+        {
+            return Err(usage_error(
+                "-p/--passphrase can't be used with -r, -R, -i or -j",
+            ));
+        }
+        vec![Box::new(scrypt::Recipient::new(read_new_passphrase(py)?))
+            as Box<dyn Recipient + Send + Sync>]
+    } else {
+        recipients(py, &options)?
+    };
+
+    if recipients.is_empty() {
+        return Err(usage_error("missing recipients; use -r, -R, -i, -j or -p"));
+    }
+
+    let output = file_arg(options.output, 1);
+    if !options.armor && matches!(output, FileArg::Fd(_)) && io::stdout().is_terminal() {
+        return Err(usage_error(
+            "refusing to output binary to the terminal; did you mean to use -a/--armor?",
+        ));
+    }
+
+    let mut reader = BufReader::new(file_arg(options.input, 0).open(py)?);
+    let mut output = BufWriter::new(output.create(py, 0o600, true)?);
+
+    encrypt_stream(
+        py,
+        &recipients,
+        &mut reader,
+        &mut output,
+        options.armor,
+        1,
+        None,
+        None,
+    )?;
+
+    output.into_inner().map_err(|e| e.into_error())?.finish()?;
+
+    Ok(())
+}
+
+fn decrypt(py: Python<'_>, mut options: Options) -> PyResult<()> {
+    if options.passphrase
+        || options.armor
+        || !options.recipients.is_empty()
+        || !options.recipients_files.is_empty()
+    {
+        return Err(usage_error(
+            "-p, -a, -r and -R can't be used with -d/--decrypt",
+        ));
+    }
+
+    let reader = BufReader::new(file_arg(options.input.take(), 0).open(py)?);
+    let decryptor = Decryptor::new_buffered(ArmoredReader::new(reader))
+        .map_err(|e| DecryptError::new_err(e.to_string()))?;
+
+    let identities: Vec<Box<dyn Identity>> = if decryptor.is_scrypt() {
+        if !options.identities.is_empty() || !options.plugins.is_empty() {
+            return Err(usage_error(
+                "this file is passphrase-encrypted; -i and -j can't be used with it",
+            ));
+        }
+        vec![Box::new(scrypt::Identity::new(read_passphrase(
+            py,
+            "Enter passphrase: ",
+        )?))]
+    } else {
+        if options.identities.is_empty() && options.plugins.is_empty() {
+            return Err(usage_error("missing identities; use -i or -j"));
+        }
+        identities(py, &options)?
+    };
+
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref()))
+        .map_err(|e| DecryptError::new_err(e.to_string()))?;
+
+    let mut output = BufWriter::new(file_arg(options.output.take(), 1).create(py, 0o600, true)?);
+    copy::copy(py, &mut reader, &mut output, None, None, PyErr::from)?;
+    output.into_inner().map_err(|e| e.into_error())?.finish()?;
+
+    Ok(())
+}
+
+fn run(py: Python<'_>, args: Vec<OsString>) -> PyResult<()> {
+    let options = Options::parse(args)?;
+
+    if options.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    if options.version {
+        println!("pyrage {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    match (options.encrypt, options.decrypt) {
+        (true, true) => Err(usage_error("-e/--encrypt can't be used with -d/--decrypt")),
+        (_, true) => decrypt(py, options),
+        (_, false) => encrypt(py, options),
+    }
+}
+
+/// Runs the `pyrage` command with `argv`, or `sys.argv[1:]`, and returns its
+/// exit status.
+#[pyfunction]
+#[pyo3(signature = (argv=None))]
+pub(crate) fn main(py: Python<'_>, argv: Option<Vec<OsString>>) -> PyResult<i32> {
+    let argv = match argv {
+        Some(argv) => argv,
+        None => py
+            .import(pyo3::intern!(py, "sys"))?
+            .getattr(pyo3::intern!(py, "argv"))?
+            .extract::<Vec<OsString>>()?
+            .into_iter()
+            .skip(1)
+            .collect(),
+    };
+
+    // Errors are reported like `rage` reports them; anything that isn't an
+    // `Exception`, like `KeyboardInterrupt`, propagates.
+    match run(py, argv) {
+        Ok(()) => Ok(0),
+        Err(e) if e.is_instance_of::<PyException>(py) => {
+            eprintln!("error: {}", e.value(py));
+            Ok(1)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use pyo3::prelude::*;
+
+use crate::{stream::CHUNK_SIZE, CancelledError};
+
+// The default number of input bytes between progress reports.
+pub(crate) const DEFAULT_PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// Counts the bytes read through it.
+///
+/// This wraps the raw input of the file and io APIs, so that progress is
+/// reported in input bytes for both encryption and decryption.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn count(&self) -> Arc<AtomicU64> {
+        self.count.clone()
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// A Python progress callback, called as `callback(bytes_done, total)` every
+/// `interval` input bytes, and once more when the copy completes.
+pub(crate) struct Progress<'py> {
+    callback: Bound<'py, PyAny>,
+    interval: u64,
+    total: Option<u64>,
+    count: Arc<AtomicU64>,
+    next: u64,
+}
+
+impl<'py> Progress<'py> {
+    pub(crate) fn new(
+        callback: Option<Bound<'py, PyAny>>,
+        interval: u64,
+        total: Option<u64>,
+        count: Arc<AtomicU64>,
+    ) -> Option<Self> {
+        callback.map(|callback| Self {
+            callback,
+            interval: interval.max(1),
+            total,
+            count,
+            next: 0,
+        })
+    }
+
+    pub(crate) fn report(&mut self, done: bool) -> PyResult<()> {
+        let count = self.count.load(Ordering::Relaxed);
+        if done || count >= self.next {
+            self.callback.call1((count, self.total))?;
+            self.next = (count / self.interval + 1) * self.interval;
+        }
+        Ok(())
+    }
+}
+
+/// A token for cancelling file and stream operations from another thread.
+///
+/// Operations given a token check it between chunks, and raise
+/// `CancelledError` once it has been cancelled.
+#[pyclass(module = "pyrage", frozen)]
+#[derive(Default)]
+pub(crate) struct CancellationToken(AtomicBool);
+
+#[pymethods]
+impl CancellationToken {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[getter]
+    pub(crate) fn cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Raises a pending signal (e.g. `KeyboardInterrupt`), or `CancelledError`
+/// if `cancel` has been cancelled.
+pub(crate) fn check(py: Python<'_>, cancel: Option<&CancellationToken>) -> PyResult<()> {
+    py.check_signals()?;
+    if cancel.is_some_and(|token| token.cancelled()) {
+        return Err(CancelledError::new_err("operation cancelled"));
+    }
+    Ok(())
+}
+
+/// Like `std::io::copy`, but reports progress as it goes, and stops early on
+/// a pending signal (e.g. `KeyboardInterrupt`) or a cancelled token.
+///
+/// The GIL is released while each chunk is read and written, which is where
+/// the encryption or decryption happens, so that other Python threads (such
+/// as one cancelling this copy) can run in the meantime. It's only held
+/// between chunks, for the checks and the progress callback.
+///
+/// I/O errors are converted with `io_error`, so that each caller can raise
+/// its usual exception type; errors from the callback propagate as-is.
+pub(crate) fn copy<R: Read + Send, W: Write + Send>(
+    py: Python<'_>,
+    reader: &mut R,
+    writer: &mut W,
+    mut progress: Option<Progress<'_>>,
+    cancel: Option<&CancellationToken>,
+    io_error: impl Fn(io::Error) -> PyErr,
+) -> PyResult<()> {
+    let mut buf = vec![0; CHUNK_SIZE];
+
+    loop {
+        check(py, cancel)?;
+
+        let n = match py.allow_threads(|| reader.read(&mut buf)) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(io_error(e)),
+        };
+        py.allow_threads(|| writer.write_all(&buf[..n]))
+            .map_err(&io_error)?;
+
+        if let Some(progress) = progress.as_mut() {
+            progress.report(false)?;
+        }
+    }
+
+    match progress.as_mut() {
+        Some(progress) => progress.report(true),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use pyo3::{
+    prelude::*,
+    types::{IntoPyDict, PyInt},
+};
+use pyo3_file::PyFileLikeObject;
+use tempfile::NamedTempFile;
+
+/// A filesystem path, given as a `str`, `bytes`, or `os.PathLike` of either.
+///
+/// Going through `os.fsdecode` means that `bytes` paths that aren't valid
+/// UTF-8 still round-trip exactly.
+pub(crate) struct FsPath(pub(crate) PathBuf);
+
+impl<'source> FromPyObject<'source> for FsPath {
+    fn extract_bound(ob: &Bound<'source, PyAny>) -> PyResult<Self> {
+        let py = ob.py();
+        let path = py
+            .import(pyo3::intern!(py, "os"))?
+            .call_method1(pyo3::intern!(py, "fsdecode"), (ob,))?;
+
+        Ok(Self(path.extract()?))
+    }
+}
+
+/// A file argument to the `*_file` APIs: either a path, or a file descriptor
+/// that's already open.
+///
+/// Descriptors are used from their current offset, and are left open.
+#[derive(Clone)]
+pub(crate) enum FileArg {
+    Path(PathBuf),
+    Fd(i32),
+}
+
+impl<'source> FromPyObject<'source> for FileArg {
+    fn extract_bound(ob: &Bound<'source, PyAny>) -> PyResult<Self> {
+        if ob.is_instance_of::<PyInt>() {
+            Ok(Self::Fd(ob.extract()?))
+        } else {
+            Ok(Self::Path(ob.extract::<FsPath>()?.0))
+        }
+    }
+}
+
+// Wrapping a descriptor in a Python file object keeps us from having to take
+// (unsafe) ownership of it on the Rust side.
+fn fdopen(py: Python<'_>, fd: i32, mode: &str) -> PyResult<PyFileLikeObject> {
+    let kwargs = [("closefd", false)].into_py_dict(py)?;
+    let file = py.import(pyo3::intern!(py, "os"))?.call_method(
+        pyo3::intern!(py, "fdopen"),
+        (fd, mode, 0),
+        Some(&kwargs),
+    )?;
+
+    PyFileLikeObject::py_new(file)
+}
+
+impl FileArg {
+    /// The size of this input, if it's a regular file given by path.
+    pub(crate) fn size(&self) -> Option<u64> {
+        match self {
+            Self::Path(path) => std::fs::metadata(path)
+                .ok()
+                .filter(|m| m.is_file())
+                .map(|m| m.len()),
+            Self::Fd(_) => None,
+        }
+    }
+
+    pub(crate) fn open(self, py: Python<'_>) -> PyResult<Box<dyn Read + Send>> {
+        match self {
+            Self::Path(path) => Ok(Box::new(File::open(path)?)),
+            Self::Fd(fd) => Ok(Box::new(fdopen(py, fd, "rb")?)),
+        }
+    }
+
+    /// Creates an output for this argument. Paths are written atomically; see
+    /// `AtomicFile`.
+    pub(crate) fn create(self, py: Python<'_>, mode: u32, overwrite: bool) -> PyResult<Output> {
+        match self {
+            Self::Path(path) => Ok(Output::Atomic(AtomicFile::create(&path, mode, overwrite)?)),
+            Self::Fd(fd) => Ok(Output::Fd(fdopen(py, fd, "wb")?)),
+        }
+    }
+}
+
+/// The output half of a `FileArg`.
+pub(crate) enum Output {
+    Atomic(AtomicFile),
+    Fd(PyFileLikeObject),
+}
+
+impl Output {
+    /// Completes the output once everything has been written to it.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Atomic(file) => file.persist(),
+            Self::Fd(mut file) => file.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Atomic(file) => file.write(buf),
+            Self::Fd(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Atomic(file) => file.flush(),
+            Self::Fd(file) => file.flush(),
+        }
+    }
+}
+
+/// An output file that only appears at its destination once it's complete.
+///
+/// Everything is written to a temporary file in the destination's directory,
+/// which `persist` then syncs and renames into place. Dropping an
+/// `AtomicFile` without persisting it removes the temporary file, leaving
+/// any existing destination untouched.
+pub(crate) struct AtomicFile {
+    file: NamedTempFile,
+    path: PathBuf,
+    mode: u32,
+    overwrite: bool,
+}
+
+impl AtomicFile {
+    pub(crate) fn create(path: &Path, mode: u32, overwrite: bool) -> io::Result<Self> {
+        // Fail before doing any work; `persist` checks again, atomically.
+        if !overwrite && path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let file = tempfile::Builder::new()
+            .prefix(".pyrage-")
+            .tempfile_in(dir)?;
+
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+            mode,
+            overwrite,
+        })
+    }
+
+    pub(crate) fn persist(self) -> io::Result<()> {
+        set_mode(self.file.as_file(), self.mode)?;
+        self.file.as_file().sync_all()?;
+
+        let persisted = match self.overwrite {
+            true => self.file.persist(&self.path),
+            false => self.file.persist_noclobber(&self.path),
+        };
+        persisted.map_err(|e| e.error)?;
+
+        sync_parent(&self.path)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+}
+
+// Windows has no mode bits; files get the ACLs of their directory.
+#[cfg(not(unix))]
+fn set_mode(_file: &File, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+// Makes the rename itself durable.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::iter;
+use std::slice;
+use std::sync::OnceLock;
+
+use age::{DecryptError as RageDecryptError, Identity};
+use age_core::format::{FileKey, Stanza};
+use pyo3::prelude::*;
+
+use crate::{decryptor_error, keys::Identities, DecryptError};
+
+/// Which of the given identities opened a file, and how.
+#[pyclass(module = "pyrage", frozen)]
+#[derive(Clone)]
+pub(crate) struct DecryptInfo {
+    identity_index: usize,
+    stanza_tag: Option<String>,
+    stanza_count: usize,
+}
+
+#[pymethods]
+impl DecryptInfo {
+    #[getter]
+    pub(crate) fn identity_index(&self) -> usize {
+        self.identity_index
+    }
+
+    #[getter]
+    fn stanza_tag(&self) -> Option<&str> {
+        self.stanza_tag.as_deref()
+    }
+
+    #[getter]
+    fn stanza_count(&self) -> usize {
+        self.stanza_count
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "DecryptInfo(identity_index={}, stanza_tag={}, stanza_count={})",
+            self.identity_index,
+            match &self.stanza_tag {
+                Some(tag) => format!("{tag:?}"),
+                None => "None".into(),
+            },
+            self.stanza_count
+        )
+    }
+}
+
+/// Tries each of `identities` in turn, as `age` would, and remembers the
+/// first one that unwraps the file key, or what the header held if none did.
+pub(crate) struct Tracker<'a> {
+    identities: &'a Identities,
+    info: OnceLock<DecryptInfo>,
+    stanzas: OnceLock<Vec<String>>,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(identities: &'a Identities) -> Self {
+        Self {
+            identities,
+            info: OnceLock::new(),
+            stanzas: OnceLock::new(),
+        }
+    }
+
+    /// Only meaningful once decryption has succeeded, which means one of
+    /// the identities matched.
+    pub(crate) fn info(self) -> DecryptInfo {
+        self.info
+            .into_inner()
+            .expect("a decrypted file has a matching identity")
+    }
+
+    /// Converts a decryption failure into a `DecryptError`. When no identity
+    /// matched, the message lists what the file is encrypted to, and the
+    /// types of the identities that were tried.
+    pub(crate) fn error(&self, e: RageDecryptError) -> PyErr {
+        let stanzas = match (&e, self.stanzas.get()) {
+            (RageDecryptError::NoMatchingKeys, Some(stanzas)) => stanzas,
+            _ => return decryptor_error(e),
+        };
+
+        let identities = match &self.identities[..] {
+            [] => "none".into(),
+            identities => identities
+                .iter()
+                .map(|i| format!("pyrage.{}", i.type_name().replace("::", ".")))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+
+        DecryptError::new_err(format!(
+            "{e}\nThe file is encrypted to {} stanza(s):\n  {}\nIdentities tried: {identities}",
+            stanzas.len(),
+            stanzas.join("\n  "),
+        ))
+    }
+}
+
+impl Identity for Tracker<'_> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, RageDecryptError>> {
+        self.unwrap_stanzas(slice::from_ref(stanza))
+    }
+
+    fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, RageDecryptError>> {
+        // Only the tag and arguments, which are public; bodies hold the
+        // wrapped file key.
+        let _ = self.stanzas.set(
+            stanzas
+                .iter()
+                .map(|s| {
+                    iter::once(&s.tag)
+                        .chain(&s.args)
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect(),
+        );
+
+        self.identities
+            .iter()
+            .enumerate()
+            .find_map(|(position, identity)| {
+                // Plugins see every stanza at once and don't say which one
+                // they used.
+                let (stanza_tag, result) = if identity.unwraps_each_stanza() {
+                    stanzas.iter().find_map(|stanza| {
+                        let result = identity.unwrap_stanza(stanza)?;
+                        Some((Some(stanza.tag.clone()), result))
+                    })?
+                } else {
+                    (None, identity.unwrap_stanzas(stanzas)?)
+                };
+
+                if result.is_ok() {
+                    let _ = self.info.set(DecryptInfo {
+                        identity_index: self.identities.index(position),
+                        stanza_tag,
+                        stanza_count: stanzas.len(),
+                    });
+                }
+                Some(result)
+            })
+    }
+}
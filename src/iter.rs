@@ -0,0 +1,283 @@
+//! Encryption and decryption over iterables of chunks, for streaming
+//! responses and other producers that aren't file-like.
+
+use std::io::{self, BufReader, Read, Write};
+use std::sync::{Arc, Mutex, TryLockError};
+
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    stream::{StreamReader, StreamWriter},
+    Encryptor, Identity, Recipient,
+};
+use pyo3::{
+    exceptions::PyValueError,
+    prelude::*,
+    types::{PyBytes, PyIterator},
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    armor::{InputFormat, Peeked},
+    buffer::Buffer,
+    decryptor_error, encryptor_error,
+    info::Tracker,
+    io_error,
+    keys::{Identities, Recipients},
+    limits::{HeaderLimit, Limits, PlaintextLimit},
+    stream::CHUNK_SIZE,
+    DecryptError, EncryptError,
+};
+
+fn encrypt_error(e: io::Error) -> PyErr {
+    io_error(e, EncryptError::new_err)
+}
+
+fn decrypt_error(e: io::Error) -> PyErr {
+    io_error(e, DecryptError::new_err)
+}
+
+// Like a generator, each iterator can only be advanced by one caller at a
+// time. Blocking instead could deadlock with the GIL held.
+fn lock<T>(state: &Mutex<T>) -> PyResult<std::sync::MutexGuard<'_, T>> {
+    state.try_lock().map_err(|e| match e {
+        TryLockError::WouldBlock => PyValueError::new_err("generator already executing"),
+        TryLockError::Poisoned(_) => PyValueError::new_err("generator is in an invalid state"),
+    })
+}
+
+/// The encrypted output so far, shared with the writer that produces it.
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Sink {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().expect("sink lock"))
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("sink lock").extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct EncryptState {
+    chunks: Py<PyIterator>,
+    // `None` once the input is exhausted and the file finished.
+    writer: Option<StreamWriter<ArmoredWriter<Sink>>>,
+}
+
+/// The iterator returned by `encrypt_iter`.
+#[pyclass(module = "pyrage", frozen)]
+pub(crate) struct EncryptIter {
+    sink: Sink,
+    state: Mutex<EncryptState>,
+}
+
+impl EncryptIter {
+    fn advance<'p>(
+        &self,
+        py: Python<'p>,
+        state: &mut EncryptState,
+    ) -> PyResult<Option<Bound<'p, PyBytes>>> {
+        loop {
+            let encrypted = self.sink.take();
+            if !encrypted.is_empty() {
+                return Ok(Some(PyBytes::new(py, &encrypted)));
+            }
+
+            let Some(stream) = state.writer.as_mut() else {
+                return Ok(None);
+            };
+            match state.chunks.bind(py).clone().next() {
+                Some(chunk) => {
+                    let chunk = chunk?.extract::<Buffer<'_>>()?;
+                    io::copy(&mut chunk.reader(), stream).map_err(encrypt_error)?;
+                }
+                None => {
+                    let stream = state.writer.take().expect("writer is still open");
+                    stream
+                        .finish()
+                        .map_err(encrypt_error)?
+                        .finish()
+                        .map_err(encrypt_error)?;
+                }
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl EncryptIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'p>(&self, py: Python<'p>) -> PyResult<Option<Bound<'p, PyBytes>>> {
+        let mut state = lock(&self.state)?;
+
+        let result = self.advance(py, &mut state);
+        // After an error, the input can't be trusted to be complete, so the
+        // file must never be finished: that would make a truncated plaintext
+        // look like a whole one.
+        if result.is_err() {
+            state.writer = None;
+            self.sink.take();
+        }
+        result
+    }
+}
+
+/// Encrypts an iterable of bytes-like chunks, yielding the encrypted file
+/// as each STREAM chunk is completed. The header is yielded first.
+#[pyfunction]
+#[pyo3(signature = (chunks, recipients, armored=false))]
+pub(crate) fn encrypt_iter(
+    chunks: &Bound<'_, PyAny>,
+    recipients: Recipients,
+    armored: bool,
+) -> PyResult<EncryptIter> {
+    let chunks = chunks.try_iter()?.unbind();
+    let recipients = recipients
+        .into_iter()
+        .map(|pr| pr.as_recipient())
+        .collect::<Vec<_>>();
+
+    let encryptor =
+        Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as &dyn Recipient))
+            .map_err(encryptor_error)?;
+
+    let format = match armored {
+        true => Format::AsciiArmor,
+        false => Format::Binary,
+    };
+    let sink = Sink::default();
+    let writer = encryptor
+        .wrap_output(ArmoredWriter::wrap_output(sink.clone(), format)?)
+        .map_err(|e| EncryptError::new_err(e.to_string()))?;
+
+    Ok(EncryptIter {
+        sink,
+        state: Mutex::new(EncryptState {
+            chunks,
+            writer: Some(writer),
+        }),
+    })
+}
+
+/// Reads the chunks of a Python iterable as one continuous input.
+struct ChunkReader {
+    chunks: Py<PyIterator>,
+    chunk: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() && !self.done {
+            Python::with_gil(|py| -> PyResult<()> {
+                match self.chunks.bind(py).clone().next() {
+                    Some(chunk) => {
+                        self.chunk.clear();
+                        self.pos = 0;
+                        chunk?
+                            .extract::<Buffer<'_>>()?
+                            .reader()
+                            .read_to_end(&mut self.chunk)?;
+                    }
+                    None => self.done = true,
+                }
+                Ok(())
+            })?;
+        }
+
+        let available = &self.chunk[self.pos..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+type PlaintextReader =
+    PlaintextLimit<StreamReader<HeaderLimit<ArmoredReader<BufReader<Peeked<ChunkReader>>>>>>;
+
+/// The iterator returned by `decrypt_iter`.
+#[pyclass(module = "pyrage", frozen)]
+pub(crate) struct DecryptIter(Mutex<Option<PlaintextReader>>);
+
+#[pymethods]
+impl DecryptIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'p>(&self, py: Python<'p>) -> PyResult<Option<Bound<'p, PyBytes>>> {
+        let mut state = lock(&self.0)?;
+        let Some(reader) = state.as_mut() else {
+            return Ok(None);
+        };
+
+        let mut decrypted = Zeroizing::new(vec![0; CHUNK_SIZE]);
+        let n = loop {
+            match reader.read(&mut decrypted) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+
+        match n {
+            Ok(0) => {
+                *state = None;
+                Ok(None)
+            }
+            Ok(n) => Ok(Some(PyBytes::new(py, &decrypted[..n]))),
+            // As with a generator, there's no more output after an error.
+            Err(e) => {
+                *state = None;
+                Err(decrypt_error(e))
+            }
+        }
+    }
+}
+
+/// Decrypts an iterable of bytes-like chunks holding an age file, yielding
+/// the plaintext a STREAM chunk at a time.
+///
+/// The header is read, and the identities tried, as soon as this is called,
+/// so that a file that can't be decrypted fails before there's any output.
+/// Each chunk is only yielded once it has been authenticated, but the file
+/// as a whole isn't until the last one has been.
+#[pyfunction]
+#[pyo3(signature = (chunks, identities, format=InputFormat::Auto, *, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+pub(crate) fn decrypt_iter(
+    chunks: &Bound<'_, PyAny>,
+    identities: Identities,
+    format: InputFormat,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<DecryptIter> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+    let reader = format.check_reader(ChunkReader {
+        chunks: chunks.try_iter()?.unbind(),
+        chunk: vec![],
+        pos: 0,
+        done: false,
+    })?;
+    let tracker = Tracker::new(&identities);
+
+    let decryptor = age::Decryptor::new_buffered(limits.header(ArmoredReader::new(reader)))
+        .map_err(decryptor_error)?;
+    let reader = decryptor
+        .decrypt(std::iter::once(&tracker as &dyn Identity))
+        .map_err(|e| tracker.error(e))?;
+
+    Ok(DecryptIter(Mutex::new(Some(limits.plaintext(reader)))))
+}
@@ -1,8 +1,8 @@
 #![deny(unsafe_code)]
 
 use std::collections::HashSet;
-use std::io::Write;
-use std::{fs::File, io::Read};
+use std::io::Read;
+use std::io::{self as io, BufRead, Write};
 
 use age::{
     armor::ArmoredReader, armor::ArmoredWriter, armor::Format, DecryptError as RageDecryptError,
@@ -14,13 +14,39 @@ use pyo3::{
     exceptions::{PyException, PyTypeError},
     prelude::*,
     py_run,
-    types::PyBytes,
+    types::{PyBytes, PyString},
 };
 use pyo3_file::PyFileLikeObject;
-
+use zeroize::Zeroizing;
+
+use crate::armor::InputFormat;
+use crate::buffer::{Buffer, BufferMut, Ciphertext};
+use crate::copy::{CancellationToken, CountingReader, Progress, DEFAULT_PROGRESS_INTERVAL};
+use crate::file::FileArg;
+use crate::info::{DecryptInfo, Tracker};
+use crate::keys::{Identities, Recipients};
+use crate::limits::Limits;
+use crate::secret::{OutputType, SecretBuffer, SecretVec};
+
+mod armor;
+mod batch;
+mod buffer;
+mod cli;
+mod copy;
+mod file;
+mod info;
+mod iter;
+mod keys;
+mod limits;
 mod passphrase;
+mod pinentry;
 mod plugin;
+mod reencrypt;
+mod rewrap;
+mod secret;
 mod ssh;
+mod stream;
+mod verify;
 mod x25519;
 
 // These exceptions are raised by the `pyrage.ssh` and `pyrage.x25519` APIs,
@@ -31,17 +57,26 @@ create_exception!(pyrage, IdentityError, PyException);
 // This is a wrapper trait for age's `Recipient`, providing trait downcasting.
 //
 // We need this so that we can pass multiple different types of recipients
-// into the Python-level `encrypt` API.
-trait PyrageRecipient: Recipient {
-    fn as_recipient(self: Box<Self>) -> Box<dyn Recipient>;
+// into the Python-level `encrypt` API. Recipients are `Send + Sync` so that
+// the batch APIs can share them between worker threads.
+trait PyrageRecipient: Recipient + Send + Sync {
+    fn as_recipient(self: Box<Self>) -> Box<dyn Recipient + Send + Sync>;
 }
 
 // This is a wrapper trait for age's `Identity`, providing trait downcasting.
 //
 // We need this so that we can pass multiple different types of identities
-// into the Python-level `decrypt` API.
-trait PyrageIdentity: Identity {
+// into the Python-level `decrypt` API. As with recipients, identities are
+// `Send + Sync` for the batch APIs.
+trait PyrageIdentity: Identity + Send + Sync {
     fn as_identity(&self) -> &dyn Identity;
+
+    // Whether `unwrap_stanzas` just tries `unwrap_stanza` on each stanza in
+    // turn, so that `DecryptInfo` can tell which stanza matched.
+    fn unwraps_each_stanza(&self) -> bool;
+
+    // The identity's type, relative to the `pyrage` module, for diagnostics.
+    fn type_name(&self) -> &'static str;
 }
 
 // This macro generates two trait impls for each passed in type:
@@ -58,8 +93,8 @@ macro_rules! recipient_traits {
             }
 
             impl PyrageRecipient for $t {
-                fn as_recipient(self: Box<Self>) -> Box<dyn Recipient> {
-                    self as Box<dyn Recipient + Send>
+                fn as_recipient(self: Box<Self>) -> Box<dyn Recipient + Send + Sync> {
+                    self as Box<dyn Recipient + Send + Sync>
                 }
             }
         )*
@@ -72,25 +107,43 @@ recipient_traits!(ssh::Recipient, x25519::Recipient, plugin::RecipientPluginV1);
 //
 // * An age `Identity` impl, using the underlying trait impl.
 // * A `PyrageIdentity` impl, by borrowing the instance and downcasting.
+//
+// Each type is paired with whether it unwraps stanzas one at a time.
 macro_rules! identity_traits {
-    ($($t:ty),+) => {
+    ($($t:ty: $each:literal),+) => {
         $(
             impl Identity for $t {
                 fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, RageDecryptError>> {
                     self.0.unwrap_stanza(stanza)
                 }
+
+                fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, RageDecryptError>> {
+                    self.0.unwrap_stanzas(stanzas)
+                }
             }
 
             impl PyrageIdentity for $t {
                 fn as_identity(&self) -> &dyn Identity {
                     self as &dyn Identity
                 }
+
+                fn unwraps_each_stanza(&self) -> bool {
+                    $each
+                }
+
+                fn type_name(&self) -> &'static str {
+                    stringify!($t)
+                }
             }
         )*
     }
 }
 
-identity_traits!(ssh::Identity, x25519::Identity, plugin::IdentityPluginV1);
+identity_traits!(
+    ssh::Identity: true,
+    x25519::Identity: true,
+    plugin::IdentityPluginV1: false
+);
 
 // This is where the magic happens, and why we need to do the trait dance
 // above: `FromPyObject` is a third-party trait, so we need to implement it
@@ -107,6 +160,8 @@ impl<'source> FromPyObject<'source> for Box<dyn PyrageRecipient> {
             Ok(Box::new(recipient) as Box<dyn PyrageRecipient>)
         } else if let Ok(recipient) = ob.extract::<plugin::RecipientPluginV1>() {
             Ok(Box::new(recipient) as Box<dyn PyrageRecipient>)
+        } else if let Ok(s) = ob.downcast::<PyString>() {
+            keys::parse_recipient(ob.py(), &s.to_cow()?)?.extract()
         } else {
             Err(PyTypeError::new_err(
                 "invalid type (expected a recipient type)",
@@ -125,6 +180,8 @@ impl<'source> FromPyObject<'source> for Box<dyn PyrageIdentity> {
             Ok(Box::new(identity) as Box<dyn PyrageIdentity>)
         } else if let Ok(identity) = ob.extract::<plugin::IdentityPluginV1>() {
             Ok(Box::new(identity) as Box<dyn PyrageIdentity>)
+        } else if let Ok(s) = ob.downcast::<PyString>() {
+            keys::parse_identity(ob.py(), &s.to_cow()?)?.extract()
         } else {
             Err(PyTypeError::new_err(
                 "invalid type (expected an identity type)",
@@ -135,14 +192,23 @@ impl<'source> FromPyObject<'source> for Box<dyn PyrageIdentity> {
 
 create_exception!(pyrage, EncryptError, PyException);
 
-#[pyfunction]
-#[pyo3(signature = (plaintext, recipients, armored=false))]
-fn encrypt<'p>(
-    py: Python<'p>,
-    plaintext: &[u8],
-    recipients: Vec<Box<dyn PyrageRecipient>>,
+// Recipients wrap the file key as soon as the encryptor is created, so this
+// is where plugin failures surface too.
+fn encryptor_error(e: RageEncryptError) -> PyErr {
+    match e {
+        RageEncryptError::MissingRecipients => {
+            EncryptError::new_err("expected at least one recipient")
+        }
+        e => EncryptError::new_err(e.to_string()),
+    }
+}
+
+/// Encrypts all of `plaintext` in memory, for `encrypt` and `encrypt_armored`.
+fn encrypt_buffer(
+    plaintext: Buffer<'_>,
+    recipients: Recipients,
     armored: bool,
-) -> PyResult<Bound<'p, PyBytes>> {
+) -> PyResult<Vec<u8>> {
     // This turns each `dyn PyrageRecipient` into a `dyn Recipient`, which
     // is what the underlying `age` API expects.
     let recipients = recipients
@@ -150,8 +216,9 @@ fn encrypt<'p>(
         .map(|pr| pr.as_recipient())
         .collect::<Vec<_>>();
 
-    let encryptor = Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref()))
-        .map_err(|_| EncryptError::new_err("expected at least one recipient"))?;
+    let encryptor =
+        Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as &dyn Recipient))
+            .map_err(encryptor_error)?;
     let mut encrypted = vec![];
 
     let mut writer = match armored {
@@ -166,8 +233,7 @@ fn encrypt<'p>(
             .map_err(|e| EncryptError::new_err(e.to_string()))?,
     };
 
-    writer
-        .write_all(plaintext)
+    std::io::copy(&mut plaintext.reader(), &mut writer)
         .map_err(|e| EncryptError::new_err(e.to_string()))?;
     writer
         .finish()
@@ -175,17 +241,100 @@ fn encrypt<'p>(
         .finish()
         .map_err(|e| EncryptError::new_err(e.to_string()))?;
 
+    Ok(encrypted)
+}
+
+#[pyfunction]
+#[pyo3(signature = (plaintext, recipients, armored=false))]
+fn encrypt<'p>(
+    py: Python<'p>,
+    plaintext: Buffer<'_>,
+    recipients: Recipients,
+    armored: bool,
+) -> PyResult<Bound<'p, PyBytes>> {
+    let encrypted = encrypt_buffer(plaintext, recipients, armored)?;
+
     // TODO: Avoid this copy. Maybe PyBytes::new_with?
     Ok(PyBytes::new(py, &encrypted))
 }
 
 #[pyfunction]
-#[pyo3(signature = (infile, outfile, recipients, armored=false))]
+fn encrypt_armored(plaintext: Buffer<'_>, recipients: Recipients) -> PyResult<String> {
+    let encrypted = encrypt_buffer(plaintext, recipients, true)?;
+
+    // Armor is always ASCII.
+    String::from_utf8(encrypted).map_err(|e| EncryptError::new_err(e.to_string()))
+}
+
+/// Encrypts `reader` into `output`, on `threads` threads if more than one.
+#[allow(clippy::too_many_arguments)]
+fn encrypt_stream<R: Read + Send, W: Write + Send>(
+    py: Python<'_>,
+    recipients: &[Box<dyn Recipient + Send + Sync>],
+    reader: &mut R,
+    output: W,
+    armored: bool,
+    threads: usize,
+    progress: Option<Progress<'_>>,
+    cancel: Option<&CancellationToken>,
+) -> PyResult<W> {
+    let recipients = recipients
+        .iter()
+        .map(|r| r.as_ref() as &dyn Recipient)
+        .collect::<Vec<_>>();
+
+    let format = match armored {
+        true => Format::AsciiArmor,
+        false => Format::Binary,
+    };
+    let mut output = ArmoredWriter::wrap_output(output, format)?;
+
+    if threads > 1 {
+        stream::encrypt(
+            py,
+            &recipients,
+            reader,
+            &mut output,
+            threads,
+            progress,
+            cancel,
+        )?;
+    } else {
+        let encryptor =
+            Encryptor::with_recipients(recipients.into_iter()).map_err(encryptor_error)?;
+        let mut writer = encryptor
+            .wrap_output(&mut output)
+            .map_err(|e| EncryptError::new_err(e.to_string()))?;
+
+        copy::copy(py, reader, &mut writer, progress, cancel, |e| {
+            EncryptError::new_err(e.to_string())
+        })?;
+
+        writer
+            .finish()
+            .map_err(|e| EncryptError::new_err(e.to_string()))?;
+    }
+
+    output
+        .finish()
+        .map_err(|e| EncryptError::new_err(e.to_string()))
+}
+
+#[pyfunction]
+#[pyo3(signature = (infile, outfile, recipients, armored=false, *, mode=0o600, overwrite=true, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL, cancel=None, threads=1))]
+#[allow(clippy::too_many_arguments)]
 fn encrypt_file(
-    infile: String,
-    outfile: String,
-    recipients: Vec<Box<dyn PyrageRecipient>>,
+    py: Python<'_>,
+    infile: FileArg,
+    outfile: FileArg,
+    recipients: Recipients,
     armored: bool,
+    mode: u32,
+    overwrite: bool,
+    progress: Option<Bound<'_, PyAny>>,
+    progress_interval: u64,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    threads: usize,
 ) -> PyResult<()> {
     // This turns each `dyn PyrageRecipient` into a `dyn Recipient`, which
     // is what the underlying `age` API expects.
@@ -194,84 +343,216 @@ fn encrypt_file(
         .map(|pr| pr.as_recipient())
         .collect::<Vec<_>>();
 
-    let reader = File::open(infile)?;
-    let writer = File::create(outfile)?;
+    let threads = stream::threads(threads)?;
 
-    let mut reader = std::io::BufReader::new(reader);
-    let mut writer = std::io::BufWriter::new(writer);
+    let total = infile.size();
+    let reader = CountingReader::new(infile.open(py)?);
+    let progress = Progress::new(progress, progress_interval, total, reader.count());
+    let output = outfile.create(py, mode, overwrite)?;
 
-    let encryptor = Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref()))
-        .map_err(|_| EncryptError::new_err("expected at least one recipient"))?;
+    let mut reader = std::io::BufReader::new(reader);
+    let mut output = std::io::BufWriter::new(output);
 
-    let mut writer = match armored {
-        true => encryptor
-            .wrap_output(ArmoredWriter::wrap_output(&mut writer, Format::AsciiArmor)?)
-            .map_err(|e| EncryptError::new_err(e.to_string()))?,
-        false => encryptor
-            .wrap_output(ArmoredWriter::wrap_output(&mut writer, Format::Binary)?)
-            .map_err(|e| EncryptError::new_err(e.to_string()))?,
-    };
+    encrypt_stream(
+        py,
+        &recipients,
+        &mut reader,
+        &mut output,
+        armored,
+        threads,
+        progress,
+        cancel.as_deref(),
+    )?;
 
-    std::io::copy(&mut reader, &mut writer).map_err(|e| EncryptError::new_err(e.to_string()))?;
-
-    writer
-        .finish()
-        .map_err(|e| EncryptError::new_err(e.to_string()))?
-        .finish()
-        .map_err(|e| EncryptError::new_err(e.to_string()))?;
+    output.into_inner().map_err(|e| e.into_error())?.finish()?;
 
     Ok(())
 }
 
 create_exception!(pyrage, DecryptError, PyException);
 
+create_exception!(pyrage, CancelledError, PyException);
+
+create_exception!(pyrage, LimitExceededError, DecryptError);
+
+/// Converts an I/O error with `new_err`, unless it carries a Python exception
+/// (e.g. `LimitExceededError`, or one raised by a Python input), which is
+/// returned as it was.
+pub(crate) fn io_error(e: io::Error, new_err: fn(String) -> PyErr) -> PyErr {
+    match e.get_ref().is_some_and(|inner| inner.is::<PyErr>()) {
+        true => e.into(),
+        false => new_err(e.to_string()),
+    }
+}
+
+/// Converts an error from reading an age header into a `DecryptError`.
+pub(crate) fn decryptor_error(e: RageDecryptError) -> PyErr {
+    match e {
+        RageDecryptError::Io(e) => io_error(e, DecryptError::new_err),
+        e => DecryptError::new_err(e.to_string()),
+    }
+}
+
 #[pyfunction]
+#[pyo3(signature = (ciphertext, identities, format=InputFormat::Auto, *, return_info=false, output_type=OutputType::Bytes, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
 fn decrypt<'p>(
     py: Python<'p>,
-    ciphertext: &[u8],
-    identities: Vec<Box<dyn PyrageIdentity>>,
-) -> PyResult<Bound<'p, PyBytes>> {
-    let identities = identities.iter().map(|pi| pi.as_ref().as_identity());
-
-    let decryptor = age::Decryptor::new(ArmoredReader::new(ciphertext))
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
-
-    let mut decrypted = vec![];
-    let mut reader = decryptor
-        .decrypt(identities)
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
-    reader
+    ciphertext: Ciphertext<'_>,
+    identities: Identities,
+    format: InputFormat,
+    return_info: bool,
+    output_type: OutputType,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<Bound<'p, PyAny>> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+    let tracker = Tracker::new(&identities);
+
+    let decryptor =
+        age::Decryptor::new_buffered(limits.header(ArmoredReader::new(ciphertext.reader(format)?)))
+            .map_err(decryptor_error)?;
+
+    // The plaintext is always shorter than the ciphertext, so reserving that
+    // much up front means no stray copies are left behind by reallocation.
+    let mut decrypted = Zeroizing::new(Vec::with_capacity(ciphertext.len()));
+    let reader = decryptor
+        .decrypt(std::iter::once(&tracker as &dyn Identity))
+        .map_err(|e| tracker.error(e))?;
+    limits
+        .plaintext(reader)
         .read_to_end(&mut decrypted)
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
+        .map_err(|e| io_error(e, DecryptError::new_err))?;
 
-    // TODO: Avoid this copy. Maybe PyBytes::new_with?
-    Ok(PyBytes::new(py, &decrypted))
+    let decrypted = output_type.wrap(py, decrypted)?;
+    match return_info {
+        true => Ok((decrypted, tracker.info()).into_pyobject(py)?.into_any()),
+        false => Ok(decrypted.into_any()),
+    }
 }
 
 #[pyfunction]
-fn decrypt_file(
-    infile: String,
-    outfile: String,
-    identities: Vec<Box<dyn PyrageIdentity>>,
-) -> PyResult<()> {
-    let identities = identities.iter().map(|pi| pi.as_ref().as_identity());
+#[pyo3(signature = (ciphertext, out_buffer, identities, format=InputFormat::Auto, *, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+fn decrypt_into(
+    ciphertext: Ciphertext<'_>,
+    mut out_buffer: BufferMut<'_>,
+    identities: Identities,
+    format: InputFormat,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<usize> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+    let tracker = Tracker::new(&identities);
+
+    let decryptor =
+        age::Decryptor::new_buffered(limits.header(ArmoredReader::new(ciphertext.reader(format)?)))
+            .map_err(decryptor_error)?;
+    let reader = decryptor
+        .decrypt(std::iter::once(&tracker as &dyn Identity))
+        .map_err(|e| tracker.error(e))?;
+
+    out_buffer.copy_from(limits.plaintext(reader), |e| {
+        io_error(e, DecryptError::new_err)
+    })
+}
+
+/// Decrypts `reader` into `writer`, on `threads` threads if more than one,
+/// and reports which of `identities` opened it. Failures past the header,
+/// including a corrupted or truncated payload, go through `io_error`.
+#[allow(clippy::too_many_arguments)]
+fn decrypt_stream<R: BufRead + Send, W: Write + Send>(
+    py: Python<'_>,
+    identities: &Identities,
+    reader: R,
+    writer: &mut W,
+    limits: Limits,
+    threads: usize,
+    progress: Option<Progress<'_>>,
+    cancel: Option<&CancellationToken>,
+    io_error: fn(std::io::Error) -> PyErr,
+) -> PyResult<DecryptInfo> {
+    let tracker = Tracker::new(identities);
+    let reader = limits.header(ArmoredReader::new(reader));
+    let mut writer = limits.plaintext(writer);
+
+    if threads > 1 {
+        stream::decrypt(
+            py,
+            &tracker,
+            reader,
+            &mut writer,
+            threads,
+            progress,
+            cancel,
+            io_error,
+        )?;
+        return Ok(tracker.info());
+    }
 
-    let reader = File::open(infile)?;
-    let writer = File::create(outfile)?;
+    let decryptor = age::Decryptor::new_buffered(reader).map_err(decryptor_error)?;
+    let mut reader = decryptor
+        .decrypt(std::iter::once(&tracker as &dyn Identity))
+        .map_err(|e| tracker.error(e))?;
 
-    let reader = std::io::BufReader::new(reader);
-    let mut writer = std::io::BufWriter::new(writer);
+    copy::copy(py, &mut reader, &mut writer, progress, cancel, io_error)?;
 
-    let decryptor = age::Decryptor::new_buffered(ArmoredReader::new(reader))
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
+    Ok(tracker.info())
+}
 
-    let mut reader = decryptor
-        .decrypt(identities)
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
+#[pyfunction]
+#[pyo3(signature = (infile, outfile, identities, format=InputFormat::Auto, *, mode=0o600, overwrite=true, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL, cancel=None, threads=1, return_info=false, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
+fn decrypt_file(
+    py: Python<'_>,
+    infile: FileArg,
+    outfile: FileArg,
+    identities: Identities,
+    format: InputFormat,
+    mode: u32,
+    overwrite: bool,
+    progress: Option<Bound<'_, PyAny>>,
+    progress_interval: u64,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    threads: usize,
+    return_info: bool,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<Option<DecryptInfo>> {
+    let threads = stream::threads(threads)?;
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+
+    let total = infile.size();
+    let reader = CountingReader::new(infile.open(py)?);
+    let progress = Progress::new(progress, progress_interval, total, reader.count());
+    let reader = format.check_reader(std::io::BufReader::new(reader))?;
+
+    let writer = outfile.create(py, mode, overwrite)?;
+    let mut writer = std::io::BufWriter::new(writer);
 
-    std::io::copy(&mut reader, &mut writer)?;
+    let info = decrypt_stream(
+        py,
+        &identities,
+        reader,
+        &mut writer,
+        limits,
+        threads,
+        progress,
+        cancel.as_deref(),
+        PyErr::from,
+    )?;
+
+    writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+
+    Ok(return_info.then_some(info))
+}
 
-    Ok(())
+/// Whether `file` is a text stream, which can only carry an armored age file.
+fn is_text_io(py: Python<'_>, file: &PyObject) -> PyResult<bool> {
+    let text_io = py.import("io")?.getattr("TextIOBase")?;
+    file.bind(py).is_instance(&text_io)
 }
 
 fn from_pyobject(file: PyObject, read_only: bool) -> PyResult<PyFileLikeObject> {
@@ -280,12 +561,18 @@ fn from_pyobject(file: PyObject, read_only: bool) -> PyResult<PyFileLikeObject>
 }
 
 #[pyfunction]
-#[pyo3(signature = (reader, writer, recipients, armored=false))]
+#[pyo3(signature = (reader, writer, recipients, armored=false, *, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL, cancel=None, threads=1))]
+#[allow(clippy::too_many_arguments)]
 fn encrypt_io(
+    py: Python<'_>,
     reader: PyObject,
     writer: PyObject,
-    recipients: Vec<Box<dyn PyrageRecipient>>,
+    recipients: Recipients,
     armored: bool,
+    progress: Option<Bound<'_, PyAny>>,
+    progress_interval: u64,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    threads: usize,
 ) -> PyResult<()> {
     // This turns each `dyn PyrageRecipient` into a `dyn Recipient`, which
     // is what the underlying `age` API expects.
@@ -293,55 +580,113 @@ fn encrypt_io(
         .into_iter()
         .map(|pr| pr.as_recipient())
         .collect::<Vec<_>>();
-    let reader = from_pyobject(reader, true)?;
+    let threads = stream::threads(threads)?;
+    if !armored && is_text_io(py, &writer)? {
+        return Err(PyTypeError::new_err(
+            "writing to a text stream requires armored=True",
+        ));
+    }
+    let reader = CountingReader::new(from_pyobject(reader, true)?);
+    let progress = Progress::new(progress, progress_interval, None, reader.count());
     let writer = from_pyobject(writer, false)?;
     let mut reader = std::io::BufReader::new(reader);
     let mut writer = std::io::BufWriter::new(writer);
 
-    let encryptor = Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref()))
-        .map_err(|_| EncryptError::new_err("expected at least one recipient"))?;
-
-    let mut writer = match armored {
-        true => encryptor
-            .wrap_output(ArmoredWriter::wrap_output(&mut writer, Format::AsciiArmor)?)
-            .map_err(|e| EncryptError::new_err(e.to_string()))?,
-        false => encryptor
-            .wrap_output(ArmoredWriter::wrap_output(&mut writer, Format::Binary)?)
-            .map_err(|e| EncryptError::new_err(e.to_string()))?,
-    };
-
-    std::io::copy(&mut reader, &mut writer).map_err(|e| EncryptError::new_err(e.to_string()))?;
-
-    writer
-        .finish()
-        .map_err(|e| EncryptError::new_err(e.to_string()))?
-        .finish()
-        .map_err(|e| EncryptError::new_err(e.to_string()))?;
+    encrypt_stream(
+        py,
+        &recipients,
+        &mut reader,
+        &mut writer,
+        armored,
+        threads,
+        progress,
+        cancel.as_deref(),
+    )?
+    .flush()?;
 
     Ok(())
 }
 
 #[pyfunction]
+#[pyo3(signature = (reader, writer, identities, format=InputFormat::Auto, *, atomic=false, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL, cancel=None, threads=1, return_info=false, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
 fn decrypt_io(
+    py: Python<'_>,
     reader: PyObject,
     writer: PyObject,
-    identities: Vec<Box<dyn PyrageIdentity>>,
-) -> PyResult<()> {
-    let identities = identities.iter().map(|pi| pi.as_ref().as_identity());
-    let reader = from_pyobject(reader, true)?;
+    identities: Identities,
+    format: InputFormat,
+    atomic: bool,
+    progress: Option<Bound<'_, PyAny>>,
+    progress_interval: u64,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    threads: usize,
+    return_info: bool,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<Option<DecryptInfo>> {
+    let threads = stream::threads(threads)?;
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+    let format = match is_text_io(py, &reader)? {
+        true => format.for_text()?,
+        false => format,
+    };
+    if is_text_io(py, &writer)? {
+        return Err(PyTypeError::new_err(
+            "can't write decrypted output to a text stream",
+        ));
+    }
+    let reader = CountingReader::new(from_pyobject(reader, true)?);
+    let progress = Progress::new(progress, progress_interval, None, reader.count());
     let writer = from_pyobject(writer, false)?;
-    let reader = std::io::BufReader::new(reader);
+    let reader = format.check_reader(std::io::BufReader::new(reader))?;
     let mut writer = std::io::BufWriter::new(writer);
-    let decryptor = age::Decryptor::new_buffered(ArmoredReader::new(reader))
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
-    let mut reader = decryptor
-        .decrypt(identities)
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
-    std::io::copy(&mut reader, &mut writer)?;
-    Ok(())
+
+    // In atomic mode, nothing reaches the writer until the final chunk
+    // has been authenticated. Until then, the plaintext is held in memory,
+    // and never on disk; it's wiped once it's written, or on failure.
+    let info = if atomic {
+        let mut plaintext = SecretVec::default();
+        let info = decrypt_stream(
+            py,
+            &identities,
+            reader,
+            &mut plaintext,
+            limits,
+            threads,
+            progress,
+            cancel.as_deref(),
+            PyErr::from,
+        )?;
+        copy::copy(
+            py,
+            &mut plaintext.as_slice(),
+            &mut writer,
+            None,
+            cancel.as_deref(),
+            PyErr::from,
+        )?;
+        info
+    } else {
+        decrypt_stream(
+            py,
+            &identities,
+            reader,
+            &mut writer,
+            limits,
+            threads,
+            progress,
+            cancel.as_deref(),
+            PyErr::from,
+        )?
+    };
+    writer.flush()?;
+
+    Ok(return_info.then_some(info))
 }
 
-#[pymodule]
+#[pymodule(gil_used = false)]
 fn pyrage(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     // HACK(ww): pyO3 modules are not packages, so we need this nasty
     // `py_run!` hack to support `from pyrage import ...` and similar
@@ -379,12 +724,43 @@ fn pyrage(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
 
     m.add("EncryptError", py.get_type::<EncryptError>())?;
     m.add_wrapped(wrap_pyfunction!(encrypt))?;
+    m.add_wrapped(wrap_pyfunction!(encrypt_armored))?;
     m.add_wrapped(wrap_pyfunction!(encrypt_file))?;
     m.add_wrapped(wrap_pyfunction!(encrypt_io))?;
     m.add("DecryptError", py.get_type::<DecryptError>())?;
     m.add_wrapped(wrap_pyfunction!(decrypt))?;
+    m.add_wrapped(wrap_pyfunction!(decrypt_into))?;
+    m.add_wrapped(wrap_pyfunction!(keys::parse_recipient))?;
+    m.add_wrapped(wrap_pyfunction!(keys::parse_identity))?;
     m.add_wrapped(wrap_pyfunction!(decrypt_file))?;
     m.add_wrapped(wrap_pyfunction!(decrypt_io))?;
+    m.add_wrapped(wrap_pyfunction!(verify::can_decrypt))?;
+    m.add_wrapped(wrap_pyfunction!(verify::verify))?;
+    m.add_wrapped(wrap_pyfunction!(iter::encrypt_iter))?;
+    m.add_wrapped(wrap_pyfunction!(iter::decrypt_iter))?;
+    m.add_class::<iter::EncryptIter>()?;
+    m.add_class::<iter::DecryptIter>()?;
+    m.add_class::<DecryptInfo>()?;
+    m.add_class::<SecretBuffer>()?;
+    m.add("CancelledError", py.get_type::<CancelledError>())?;
+    m.add("LimitExceededError", py.get_type::<LimitExceededError>())?;
+    m.add_class::<CancellationToken>()?;
+
+    m.add_class::<batch::BatchResult>()?;
+    m.add_wrapped(wrap_pyfunction!(batch::encrypt_files))?;
+    m.add_wrapped(wrap_pyfunction!(batch::decrypt_files))?;
+    m.add_wrapped(wrap_pyfunction!(batch::encrypt_tree))?;
+    m.add_wrapped(wrap_pyfunction!(batch::decrypt_tree))?;
+
+    m.add_wrapped(wrap_pyfunction!(armor::armor))?;
+    m.add_wrapped(wrap_pyfunction!(armor::dearmor))?;
+    m.add_wrapped(wrap_pyfunction!(armor::is_armored))?;
+
+    m.add_wrapped(wrap_pyfunction!(rewrap::rewrap))?;
+    m.add_wrapped(wrap_pyfunction!(reencrypt::reencrypt_file))?;
+    m.add_wrapped(wrap_pyfunction!(reencrypt::reencrypt_io))?;
+
+    m.add_wrapped(wrap_pyfunction!(cli::main))?;
 
     Ok(())
 }
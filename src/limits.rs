@@ -0,0 +1,204 @@
+//! Limits on what decrypting an untrusted age file may consume.
+
+use std::io::{self, BufRead, Read, Write};
+
+use pyo3::PyErr;
+
+use crate::{LimitExceededError, RageDecryptError};
+
+fn exceeded(message: String) -> io::Error {
+    LimitExceededError::new_err(message).into()
+}
+
+/// Converts age's `ExcessiveWork`, for an scrypt stanza whose work factor is
+/// above `max_work_factor` (or, without one, too slow for this machine).
+pub(crate) fn excessive_work(e: RageDecryptError, max_work_factor: Option<u8>) -> PyErr {
+    match (e, max_work_factor) {
+        (RageDecryptError::ExcessiveWork { required, .. }, Some(max)) => {
+            LimitExceededError::new_err(format!(
+                "scrypt work factor {required} is above max_work_factor ({max})"
+            ))
+        }
+        (e, _) => LimitExceededError::new_err(e.to_string()),
+    }
+}
+
+/// The `max_plaintext_size=`, `max_header_size=` and `max_stanzas=` arguments
+/// of the decryption APIs. `None` means unlimited.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Limits {
+    pub(crate) max_plaintext_size: Option<u64>,
+    pub(crate) max_header_size: Option<usize>,
+    pub(crate) max_stanzas: Option<usize>,
+}
+
+impl Limits {
+    pub(crate) fn new(
+        max_plaintext_size: Option<u64>,
+        max_header_size: Option<usize>,
+        max_stanzas: Option<usize>,
+    ) -> Self {
+        Self {
+            max_plaintext_size,
+            max_header_size,
+            max_stanzas,
+        }
+    }
+
+    /// Wraps the (dearmored) input, so that `age` can't read more of a header
+    /// than the limits allow.
+    pub(crate) fn header<R: BufRead>(&self, inner: R) -> HeaderLimit<R> {
+        HeaderLimit {
+            inner,
+            max_size: self.max_header_size,
+            max_stanzas: self.max_stanzas,
+            scan: Scan::default(),
+        }
+    }
+
+    /// Wraps a plaintext reader or writer, which fails once more than
+    /// `max_plaintext_size` bytes have gone through it.
+    pub(crate) fn plaintext<T>(&self, inner: T) -> PlaintextLimit<T> {
+        PlaintextLimit {
+            inner,
+            max: self.max_plaintext_size,
+            total: 0,
+        }
+    }
+}
+
+/// Tracks the header's lines as they're consumed. age headers are
+/// newline-separated, with each stanza starting `-> ` and the MAC line,
+/// which ends the header, starting `---`.
+#[derive(Default)]
+struct Scan {
+    size: usize,
+    stanzas: usize,
+    line: [u8; 3],
+    line_len: usize,
+    mac_line: bool,
+    done: bool,
+}
+
+impl Scan {
+    fn feed(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            if self.done {
+                return;
+            }
+            self.size += 1;
+
+            if self.line_len < self.line.len() {
+                self.line[self.line_len] = b;
+                self.line_len += 1;
+                match &self.line[..self.line_len] {
+                    b"-> " => self.stanzas += 1,
+                    b"---" => self.mac_line = true,
+                    _ => (),
+                }
+            }
+            if b == b'\n' {
+                self.done = self.mac_line;
+                self.line_len = 0;
+            }
+        }
+    }
+}
+
+pub(crate) struct HeaderLimit<R> {
+    inner: R,
+    max_size: Option<usize>,
+    max_stanzas: Option<usize>,
+    scan: Scan,
+}
+
+impl<R: BufRead> BufRead for HeaderLimit<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.scan.done {
+            return self.inner.fill_buf();
+        }
+
+        if let Some(max) = self.max_stanzas.filter(|&max| self.scan.stanzas > max) {
+            return Err(exceeded(format!(
+                "age header has more than max_stanzas ({max}) stanzas"
+            )));
+        }
+        let budget = match self.max_size {
+            Some(max) if self.scan.size >= max => {
+                return Err(exceeded(format!(
+                    "age header is larger than max_header_size ({max} bytes)"
+                )));
+            }
+            Some(max) => max - self.scan.size,
+            None => usize::MAX,
+        };
+
+        let buf = self.inner.fill_buf()?;
+        Ok(&buf[..buf.len().min(budget)])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if !self.scan.done {
+            // The bytes being consumed are still buffered, so this doesn't
+            // read anything new.
+            if let Ok(buf) = self.inner.fill_buf() {
+                self.scan.feed(&buf[..amt.min(buf.len())]);
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+impl<R: BufRead> Read for HeaderLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.scan.done {
+            return self.inner.read(buf);
+        }
+
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+pub(crate) struct PlaintextLimit<T> {
+    inner: T,
+    max: Option<u64>,
+    total: u64,
+}
+
+impl<T> PlaintextLimit<T> {
+    fn count(&mut self, n: usize) -> io::Result<()> {
+        self.total += n as u64;
+        match self.max {
+            Some(max) if self.total > max => Err(exceeded(format!(
+                "plaintext is larger than max_plaintext_size ({max} bytes)"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for PlaintextLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count(n)?;
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for PlaintextLimit<W> {
+    // Checked before writing, so that nothing past the limit is written.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count(buf.len())?;
+        let n = self.inner.write(buf)?;
+        self.total -= (buf.len() - n) as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,24 +1,38 @@
 use std::{
-    io::{Read, Write},
+    io::{self, Read},
     iter,
 };
 
 use age::{
-    armor::ArmoredReader, armor::ArmoredWriter, armor::Format, scrypt, Decryptor, Encryptor,
+    armor::ArmoredReader, armor::ArmoredWriter, armor::Format, scrypt, secrecy::SecretString,
+    Decryptor, Encryptor,
 };
-use pyo3::{prelude::*, types::PyBytes};
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+use zeroize::Zeroizing;
 
-use crate::{DecryptError, EncryptError};
+use crate::{
+    armor::InputFormat,
+    buffer::{Buffer, Ciphertext},
+    decryptor_error, io_error,
+    limits::{self, Limits},
+    pinentry::Pinentry,
+    secret::{OutputType, Passphrase},
+    CancelledError, DecryptError, EncryptError,
+};
 
 #[pyfunction]
 #[pyo3(signature = (plaintext, passphrase, armored=false))]
 fn encrypt<'p>(
     py: Python<'p>,
-    plaintext: &[u8],
-    passphrase: &str,
+    plaintext: Buffer<'_>,
+    passphrase: Passphrase<'_>,
     armored: bool,
 ) -> PyResult<Bound<'p, PyBytes>> {
-    let encryptor = Encryptor::with_user_passphrase(passphrase.into());
+    let encryptor = Encryptor::with_user_passphrase(passphrase.secret());
     let mut encrypted = vec![];
 
     let writer_result = match armored {
@@ -34,8 +48,7 @@ fn encrypt<'p>(
 
     let mut writer = writer_result.map_err(|e| EncryptError::new_err(e.to_string()))?;
 
-    writer
-        .write_all(plaintext)
+    io::copy(&mut plaintext.reader(), &mut writer)
         .map_err(|e| EncryptError::new_err(e.to_string()))?;
 
     writer
@@ -47,30 +60,138 @@ fn encrypt<'p>(
     Ok(PyBytes::new(py, &encrypted))
 }
 
+/// Where `decrypt` gets its passphrase: a `str` or `bytearray` given up front,
+/// or a prompt that's only asked once the header shows the file is
+/// passphrase-encrypted.
+///
+/// A prompt is either a `Callbacks`-style object, whose `request_passphrase`
+/// is called, or any callable taking the same description string. Either
+/// returns the passphrase, or `None` to cancel.
+enum Source<'py> {
+    Passphrase(Passphrase<'py>),
+    Prompt(Bound<'py, PyAny>),
+}
+
+impl<'py> FromPyObject<'py> for Source<'py> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let py = ob.py();
+        if let Ok(callback) = ob.getattr(pyo3::intern!(py, "request_passphrase")) {
+            Ok(Self::Prompt(callback))
+        } else if ob.is_callable() {
+            Ok(Self::Prompt(ob.clone()))
+        } else {
+            Ok(Self::Passphrase(ob.extract().map_err(|_| {
+                PyTypeError::new_err(
+                    "invalid type (expected a str or bytearray passphrase, a callable, \
+                     or an object with a request_passphrase method)",
+                )
+            })?))
+        }
+    }
+}
+
+impl Source<'_> {
+    /// Returns the passphrase to try on the given (1-based) attempt, or
+    /// `None` if the prompt was cancelled.
+    fn passphrase(&self, attempt: u32) -> PyResult<Option<SecretString>> {
+        match self {
+            Self::Passphrase(passphrase) => Ok(Some(passphrase.secret())),
+            Self::Prompt(prompt) => {
+                let description = match attempt {
+                    1 => "Enter passphrase:",
+                    _ => "Incorrect passphrase, try again:",
+                };
+                let passphrase = prompt
+                    .call1((description,))?
+                    .extract::<Option<Passphrase<'_>>>()?;
+                Ok(passphrase.map(|passphrase| passphrase.secret()))
+            }
+        }
+    }
+}
+
 #[pyfunction]
+#[pyo3(signature = (ciphertext, passphrase, format=InputFormat::Auto, *, output_type=OutputType::Bytes, max_attempts=3, max_plaintext_size=None, max_header_size=None, max_stanzas=None, max_work_factor=None))]
+#[allow(clippy::too_many_arguments)]
 fn decrypt<'p>(
     py: Python<'p>,
-    ciphertext: &[u8],
-    passphrase: &str,
-) -> PyResult<Bound<'p, PyBytes>> {
-    let decryptor = Decryptor::new_buffered(ArmoredReader::new(ciphertext))
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
-    let mut decrypted = vec![];
-    let mut reader = decryptor
-        .decrypt(iter::once(&scrypt::Identity::new(passphrase.into()) as _))
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
-    reader
+    ciphertext: Ciphertext<'_>,
+    passphrase: Source<'_>,
+    format: InputFormat,
+    output_type: OutputType,
+    max_attempts: u32,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+    max_work_factor: Option<u8>,
+) -> PyResult<Bound<'p, PyAny>> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+    if max_attempts == 0 {
+        return Err(PyValueError::new_err("max_attempts must be at least 1"));
+    }
+    // A passphrase given up front is only ever tried once.
+    let max_attempts = match passphrase {
+        Source::Passphrase(_) => 1,
+        Source::Prompt(_) => max_attempts,
+    };
+
+    let decryptor = || {
+        Decryptor::new_buffered(limits.header(ArmoredReader::new(ciphertext.reader(format)?)))
+            .map_err(decryptor_error)
+    };
+
+    let header = decryptor()?;
+    if !header.is_scrypt() {
+        return Err(DecryptError::new_err(
+            "this file isn't passphrase-encrypted",
+        ));
+    }
+
+    let mut header = Some(header);
+    let mut attempt = 1;
+    let reader = loop {
+        let Some(secret) = passphrase.passphrase(attempt)? else {
+            return Err(CancelledError::new_err("passphrase entry cancelled"));
+        };
+        // Each attempt consumes the decryptor, so later ones re-read the
+        // header.
+        let decryptor = match header.take() {
+            Some(decryptor) => decryptor,
+            None => decryptor()?,
+        };
+
+        let mut identity = scrypt::Identity::new(secret);
+        if let Some(max_work_factor) = max_work_factor {
+            identity.set_max_work_factor(max_work_factor);
+        }
+
+        match decryptor.decrypt(iter::once(&identity as _)) {
+            Ok(reader) => break reader,
+            Err(age::DecryptError::DecryptionFailed) if attempt < max_attempts => attempt += 1,
+            Err(e @ age::DecryptError::ExcessiveWork { .. }) => {
+                return Err(limits::excessive_work(e, max_work_factor))
+            }
+            Err(e) => return Err(DecryptError::new_err(e.to_string())),
+        }
+    };
+
+    // As in `pyrage.decrypt`, this never needs to reallocate.
+    let mut decrypted = Zeroizing::new(Vec::with_capacity(ciphertext.len()));
+    limits
+        .plaintext(reader)
         .read_to_end(&mut decrypted)
-        .map_err(|e| DecryptError::new_err(e.to_string()))?;
+        .map_err(|e| io_error(e, DecryptError::new_err))?;
 
-    Ok(PyBytes::new(py, &decrypted))
+    output_type.wrap(py, decrypted)
 }
 
 pub(crate) fn module(py: Python<'_>) -> PyResult<Bound<'_, PyModule>> {
     let module = PyModule::new(py, "passphrase")?;
+    module.gil_used(false)?;
 
     module.add_wrapped(wrap_pyfunction!(encrypt))?;
     module.add_wrapped(wrap_pyfunction!(decrypt))?;
+    module.add_class::<Pinentry>()?;
 
     Ok(module)
 }
@@ -0,0 +1,210 @@
+//! A minimal client for pinentry's Assuan protocol, for desktop tools that
+//! want a graphical passphrase prompt instead of the terminal.
+
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use pyo3::prelude::*;
+use zeroize::Zeroizing;
+
+// The `GPG_ERR_CANCELED` code, which pinentry reports when the user closes or
+// cancels the dialog, and `GPG_ERR_NOT_CONFIRMED`, for the "no" button of a
+// confirmation.
+const CANCELLED: &str = "83886179";
+const NOT_CONFIRMED: &str = "83886194";
+
+enum Reply {
+    Ok(Zeroizing<Vec<u8>>),
+    Cancelled,
+    NotConfirmed,
+}
+
+fn protocol_error(message: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("pinentry: {message}"))
+}
+
+// Assuan lines can't contain raw newlines, and `%` starts an escape.
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+fn unescape(s: &str) -> Zeroizing<Vec<u8>> {
+    let mut out = Zeroizing::new(Vec::with_capacity(s.len()));
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next().unwrap_or(b'0'), bytes.next().unwrap_or(b'0')];
+                let hex = std::str::from_utf8(&hex).unwrap_or("00");
+                out.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+            }
+            b => out.push(b),
+        }
+    }
+    out
+}
+
+/// One running pinentry process, for the duration of a single prompt.
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Session {
+    fn start(program: &str, title: Option<&str>) -> io::Result<Self> {
+        let mut child = Command::new(program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = BufReader::new(child.stdout.take().expect("piped stdout"));
+
+        let mut session = Self {
+            child,
+            stdin,
+            stdout,
+        };
+        // pinentry greets with an `OK` before taking any commands.
+        session.response()?;
+        if let Some(title) = title {
+            session.command(&format!("SETTITLE {}", escape(title)))?;
+        }
+
+        Ok(session)
+    }
+
+    /// Reads a response up to its final `OK`, collecting any data lines.
+    fn response(&mut self) -> io::Result<Reply> {
+        let mut data = Zeroizing::new(vec![]);
+        loop {
+            let mut line = Zeroizing::new(String::new());
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(protocol_error("unexpected end of output"));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            if line == "OK" || line.starts_with("OK ") {
+                return Ok(Reply::Ok(data));
+            } else if let Some(payload) = line.strip_prefix("D ") {
+                data.extend_from_slice(&unescape(payload));
+            } else if let Some(error) = line.strip_prefix("ERR ") {
+                let (code, message) = error.split_once(' ').unwrap_or((error, error));
+                return match code {
+                    CANCELLED => Ok(Reply::Cancelled),
+                    NOT_CONFIRMED => Ok(Reply::NotConfirmed),
+                    _ => Err(protocol_error(message)),
+                };
+            }
+            // Status (`S`) and comment (`#`) lines carry nothing we need.
+        }
+    }
+
+    fn command(&mut self, command: &str) -> io::Result<Reply> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()?;
+        self.response()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = writeln!(self.stdin, "BYE");
+        let _ = self.child.wait();
+    }
+}
+
+/// A `Callbacks` object that asks through a pinentry program, such as
+/// `pinentry-gnome3` or `pinentry-mac`.
+///
+/// The program defaults to `$PINENTRY_PROGRAM`, then `pinentry`. Each prompt
+/// runs it once; closing or cancelling the dialog returns `None`.
+#[pyclass(module = "pyrage.passphrase", frozen)]
+pub(crate) struct Pinentry {
+    program: String,
+    title: Option<String>,
+}
+
+impl Pinentry {
+    fn session(&self) -> io::Result<Session> {
+        Session::start(&self.program, self.title.as_deref())
+    }
+
+    fn get_pin(&self, description: &str, prompt: &str) -> io::Result<Option<String>> {
+        let mut session = self.session()?;
+        session.command(&format!("SETDESC {}", escape(description)))?;
+        session.command(&format!("SETPROMPT {}", escape(prompt)))?;
+
+        let Reply::Ok(pin) = session.command("GETPIN")? else {
+            return Ok(None);
+        };
+        String::from_utf8(pin.to_vec())
+            .map(Some)
+            .map_err(|_| protocol_error("passphrase isn't valid UTF-8"))
+    }
+}
+
+#[pymethods]
+impl Pinentry {
+    #[new]
+    #[pyo3(signature = (program=None, title=None))]
+    fn new(program: Option<String>, title: Option<String>) -> Self {
+        let program = program
+            .or_else(|| env::var("PINENTRY_PROGRAM").ok())
+            .unwrap_or_else(|| "pinentry".into());
+
+        Self { program, title }
+    }
+
+    fn display_message(&self, py: Python<'_>, message: &str) -> PyResult<()> {
+        py.allow_threads(|| {
+            let mut session = self.session()?;
+            session.command(&format!("SETDESC {}", escape(message)))?;
+            session.command("MESSAGE")?;
+            Ok(())
+        })
+    }
+
+    #[pyo3(signature = (message, yes_string, no_string=None))]
+    fn confirm(
+        &self,
+        py: Python<'_>,
+        message: &str,
+        yes_string: &str,
+        no_string: Option<&str>,
+    ) -> PyResult<Option<bool>> {
+        py.allow_threads(|| {
+            let mut session = self.session()?;
+            session.command(&format!("SETDESC {}", escape(message)))?;
+            session.command(&format!("SETOK {}", escape(yes_string)))?;
+            if let Some(no_string) = no_string {
+                session.command(&format!("SETNOTOK {}", escape(no_string)))?;
+            }
+
+            Ok(match session.command("CONFIRM")? {
+                Reply::Ok(_) => Some(true),
+                Reply::NotConfirmed => Some(false),
+                Reply::Cancelled => None,
+            })
+        })
+    }
+
+    /// Prompts with pinentry's masked entry field, as it has no other kind.
+    fn request_public_string(&self, py: Python<'_>, description: &str) -> PyResult<Option<String>> {
+        py.allow_threads(|| Ok(self.get_pin(description, "Value:")?))
+    }
+
+    fn request_passphrase(&self, py: Python<'_>, description: &str) -> PyResult<Option<String>> {
+        py.allow_threads(|| Ok(self.get_pin(description, "Passphrase:")?))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Pinentry(program={:?})", self.program)
+    }
+}
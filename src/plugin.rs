@@ -1,21 +1,34 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io::{Read, Write};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
-use pyo3::{prelude::*, types::PyType};
+use age_core::plugin::{Connection, IDENTITY_V1, RECIPIENT_V1};
+use base64::{prelude::BASE64_STANDARD_NO_PAD, Engine};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyType},
+};
 
-use crate::{DecryptError, EncryptError, IdentityError, RecipientError};
+use crate::{file::FsPath, DecryptError, EncryptError, IdentityError, RecipientError};
+
+pub(crate) mod client;
 
 /// Hack, because the orphan rule would prevent us from deriving a
 /// foreign trait on a foreign object. Instead, define a newtype.
 ///
 /// Inner type is PyAny, because we do duck-typing at runtime, and
-/// declaring a protocol in the type stubs.
+/// declaring a protocol in the type stubs. Without one, plugins get age's
+/// non-interactive defaults.
 #[derive(Clone)]
-pub(crate) struct PyCallbacks(Py<PyAny>);
+pub(crate) struct PyCallbacks(Option<Py<PyAny>>);
 
 impl PyCallbacks {
     fn new(inner: Bound<'_, PyAny>) -> PyResult<Self> {
-        Ok(Self(inner.unbind()))
+        Ok(Self(Some(inner.unbind())))
     }
 }
 
@@ -25,15 +38,21 @@ impl PyCallbacks {
 // These callbacks don't look like they're supposed to fail anyway.
 impl age::Callbacks for PyCallbacks {
     fn display_message(&self, message: &str) {
+        let Some(inner) = &self.0 else {
+            return age::NoCallbacks.display_message(message);
+        };
         Python::with_gil(|py| {
-            self.0
+            inner
                 .call_method1(py, pyo3::intern!(py, "display_message"), (message,))
                 .expect("`display_message` callback error")
         });
     }
     fn confirm(&self, message: &str, yes_string: &str, no_string: Option<&str>) -> Option<bool> {
+        let Some(inner) = &self.0 else {
+            return age::NoCallbacks.confirm(message, yes_string, no_string);
+        };
         Python::with_gil(|py| {
-            self.0
+            inner
                 .call_method1(
                     py,
                     pyo3::intern!(py, "confirm"),
@@ -45,8 +64,11 @@ impl age::Callbacks for PyCallbacks {
         .expect("type error in `confirm` callback")
     }
     fn request_public_string(&self, description: &str) -> Option<String> {
+        let Some(inner) = &self.0 else {
+            return age::NoCallbacks.request_public_string(description);
+        };
         Python::with_gil(|py| {
-            self.0
+            inner
                 .call_method1(
                     py,
                     pyo3::intern!(py, "request_public_string"),
@@ -58,8 +80,11 @@ impl age::Callbacks for PyCallbacks {
         .expect("type error in `request_public_string` callback")
     }
     fn request_passphrase(&self, description: &str) -> Option<age::secrecy::SecretString> {
+        let Some(inner) = &self.0 else {
+            return age::NoCallbacks.request_passphrase(description);
+        };
         Python::with_gil(|py| {
-            self.0
+            inner
                 .call_method1(py, pyo3::intern!(py, "request_passphrase"), (description,))
                 .expect("`request_passphrase` callback error")
                 .extract::<Option<String>>(py)
@@ -69,7 +94,7 @@ impl age::Callbacks for PyCallbacks {
     }
 }
 
-#[pyclass(module = "pyrage.plugin")]
+#[pyclass(module = "pyrage.plugin", frozen)]
 #[derive(Clone)]
 pub(crate) struct Recipient(pub(crate) age::plugin::Recipient);
 
@@ -91,7 +116,7 @@ impl Recipient {
     }
 }
 
-#[pyclass(module = "pyrage.plugin")]
+#[pyclass(module = "pyrage.plugin", frozen)]
 #[derive(Clone)]
 pub(crate) struct Identity(pub(crate) age::plugin::Identity);
 
@@ -118,81 +143,555 @@ impl Identity {
     }
 }
 
-#[pyclass(module = "pyrage.plugin")]
+fn timeout(seconds: Option<f64>) -> PyResult<Option<Duration>> {
+    seconds
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(format!("invalid timeout: {e}")))
+}
+
+#[pyclass(module = "pyrage.plugin", frozen)]
 #[derive(Clone)]
-pub(crate) struct RecipientPluginV1(pub(crate) Arc<age::plugin::RecipientPluginV1<PyCallbacks>>);
+pub(crate) struct RecipientPluginV1(pub(crate) Arc<client::RecipientPluginV1<PyCallbacks>>);
+
+impl RecipientPluginV1 {
+    /// Runs `age-plugin-NAME` from `PATH` for recipients given as strings,
+    /// without any user interaction.
+    pub(crate) fn non_interactive(
+        plugin_name: &str,
+        recipients: Vec<age::plugin::Recipient>,
+    ) -> PyResult<Self> {
+        let plugin = client::Plugin::new(plugin_name, None, None, None).map_err(|binary_name| {
+            EncryptError::new_err(age::EncryptError::MissingPlugin { binary_name }.to_string())
+        })?;
+
+        Ok(Self(Arc::new(client::RecipientPluginV1 {
+            plugin,
+            recipients,
+            identities: vec![],
+            callbacks: PyCallbacks(None),
+        })))
+    }
+}
 
 #[pymethods]
 impl RecipientPluginV1 {
     #[new]
     #[pyo3(
-        text_signature = "(plugin_name: str, recipients: typing.Sequence[Recipient], identities: typing.Sequence[Identity], callbacks: Callbacks)"
+        signature = (plugin_name, recipients, identities, callbacks, *, path=None, env=None, timeout=None),
+        text_signature = "(plugin_name: str, recipients: typing.Sequence[Recipient], identities: typing.Sequence[Identity], callbacks: Callbacks, *, path: typing.Optional[os.PathLike] = None, env: typing.Optional[typing.Mapping[str, str]] = None, timeout: typing.Optional[float] = None)"
     )]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         _py: Python<'_>,
         plugin_name: &str,
         recipients: Vec<Recipient>,
         identities: Vec<Identity>,
         callbacks: Bound<'_, PyAny>,
+        path: Option<FsPath>,
+        env: Option<HashMap<OsString, OsString>>,
+        timeout: Option<f64>,
     ) -> PyResult<Self> {
-        age::plugin::RecipientPluginV1::new(
-            plugin_name,
-            recipients
+        let plugin =
+            client::Plugin::new(plugin_name, path.map(|p| p.0), env, self::timeout(timeout)?)
+                .map_err(|binary_name| {
+                    EncryptError::new_err(
+                        age::EncryptError::MissingPlugin { binary_name }.to_string(),
+                    )
+                })?;
+
+        Ok(Self(Arc::new(client::RecipientPluginV1 {
+            plugin,
+            recipients: recipients
                 .into_iter()
-                .map(|i| i.0)
-                .collect::<Vec<_>>()
-                .as_slice(),
-            identities
+                .map(|r| r.0)
+                .filter(|r| r.plugin() == plugin_name)
+                .collect(),
+            identities: identities
                 .into_iter()
                 .map(|i| i.0)
-                .collect::<Vec<_>>()
-                .as_slice(),
-            PyCallbacks::new(callbacks)?,
-        )
-        .map(Arc::new)
-        .map(Self)
-        .map_err(|err| EncryptError::new_err(err.to_string()))
+                .filter(|i| i.plugin() == plugin_name)
+                .collect(),
+            callbacks: PyCallbacks::new(callbacks)?,
+        })))
     }
 }
 
-#[pyclass(module = "pyrage.plugin")]
+#[pyclass(module = "pyrage.plugin", frozen)]
 #[derive(Clone)]
-pub(crate) struct IdentityPluginV1(pub(crate) Arc<age::plugin::IdentityPluginV1<PyCallbacks>>);
+pub(crate) struct IdentityPluginV1(pub(crate) Arc<client::IdentityPluginV1<PyCallbacks>>);
+
+impl IdentityPluginV1 {
+    /// As for `RecipientPluginV1::non_interactive`.
+    pub(crate) fn non_interactive(
+        plugin_name: &str,
+        identities: Vec<age::plugin::Identity>,
+    ) -> PyResult<Self> {
+        let plugin = client::Plugin::new(plugin_name, None, None, None).map_err(|binary_name| {
+            DecryptError::new_err(age::DecryptError::MissingPlugin { binary_name }.to_string())
+        })?;
+
+        Ok(Self(Arc::new(client::IdentityPluginV1 {
+            plugin,
+            identities,
+            callbacks: PyCallbacks(None),
+        })))
+    }
+}
 
 #[pymethods]
 impl IdentityPluginV1 {
     #[new]
     #[pyo3(
-        text_signature = "(plugin_name: str, identities: typing.Sequence[Identity], callbacks: Callbacks)"
+        signature = (plugin_name, identities, callbacks, *, path=None, env=None, timeout=None),
+        text_signature = "(plugin_name: str, identities: typing.Sequence[Identity], callbacks: Callbacks, *, path: typing.Optional[os.PathLike] = None, env: typing.Optional[typing.Mapping[str, str]] = None, timeout: typing.Optional[float] = None)"
     )]
     fn new(
         _py: Python<'_>,
         plugin_name: &str,
         identities: Vec<Identity>,
         callbacks: Bound<'_, PyAny>,
+        path: Option<FsPath>,
+        env: Option<HashMap<OsString, OsString>>,
+        timeout: Option<f64>,
     ) -> PyResult<Self> {
-        age::plugin::IdentityPluginV1::new(
-            plugin_name,
-            identities
+        let plugin =
+            client::Plugin::new(plugin_name, path.map(|p| p.0), env, self::timeout(timeout)?)
+                .map_err(|binary_name| {
+                    DecryptError::new_err(
+                        age::DecryptError::MissingPlugin { binary_name }.to_string(),
+                    )
+                })?;
+
+        Ok(Self(Arc::new(client::IdentityPluginV1 {
+            plugin,
+            identities: identities
                 .into_iter()
                 .map(|i| i.0)
-                .collect::<Vec<_>>()
-                .as_slice(),
-            PyCallbacks::new(callbacks)?,
+                .filter(|i| i.plugin() == plugin_name)
+                .collect(),
+            callbacks: PyCallbacks::new(callbacks)?,
+        })))
+    }
+}
+
+// Commands sent and received by the plugin side of the age plugin protocol.
+const CMD_ADD_RECIPIENT: &str = "add-recipient";
+const CMD_ADD_IDENTITY: &str = "add-identity";
+const CMD_WRAP_FILE_KEY: &str = "wrap-file-key";
+const CMD_EXTENSION_LABELS: &str = "extension-labels";
+const CMD_RECIPIENT_STANZA: &str = "recipient-stanza";
+const CMD_FILE_KEY: &str = "file-key";
+const CMD_ERROR: &str = "error";
+const CMD_MSG: &str = "msg";
+const CMD_CONFIRM: &str = "confirm";
+const CMD_REQUEST_PUBLIC: &str = "request-public";
+const CMD_REQUEST_SECRET: &str = "request-secret";
+
+#[pyclass(module = "pyrage.plugin", frozen)]
+pub(crate) struct Stanza {
+    #[pyo3(get)]
+    tag: String,
+    #[pyo3(get)]
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+#[pymethods]
+impl Stanza {
+    #[new]
+    fn new(tag: String, args: Vec<String>, body: Vec<u8>) -> Self {
+        Self { tag, args, body }
+    }
+
+    #[getter]
+    fn body<'p>(&self, py: Python<'p>) -> Bound<'p, PyBytes> {
+        PyBytes::new(py, &self.body)
+    }
+}
+
+// A command for the age client, along with a channel for the client's response.
+type ClientCommand = (
+    String,
+    Vec<String>,
+    Vec<u8>,
+    mpsc::Sender<age_core::plugin::Result<age_core::format::Stanza>>,
+);
+
+/// The plugin-side counterpart to `PyCallbacks`: each callback is relayed to
+/// the age client that launched the plugin.
+///
+/// The sender is taken once the bidirectional phase is over, after which any
+/// further callbacks fail.
+#[pyclass(module = "pyrage.plugin", frozen)]
+pub(crate) struct ClientCallbacks(Mutex<Option<mpsc::Sender<ClientCommand>>>);
+
+impl ClientCallbacks {
+    fn send(
+        &self,
+        py: Python<'_>,
+        command: &str,
+        metadata: Vec<String>,
+        data: &[u8],
+    ) -> PyResult<Result<age_core::format::Stanza, age_core::plugin::Error>> {
+        let sender = self
+            .0
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| PyRuntimeError::new_err("connection to age client is closed"))?;
+        let (reply, response) = mpsc::channel();
+
+        py.allow_threads(move || {
+            sender
+                .send((command.into(), metadata, data.into(), reply))
+                .ok()
+                .and_then(|_| response.recv().ok())
+        })
+        .ok_or_else(|| PyRuntimeError::new_err("connection to age client is closed"))?
+        .map_err(PyErr::from)
+    }
+
+    fn close(&self) {
+        self.0.lock().unwrap().take();
+    }
+}
+
+#[pymethods]
+impl ClientCallbacks {
+    fn display_message(&self, py: Python<'_>, message: &str) -> PyResult<()> {
+        self.send(py, CMD_MSG, vec![], message.as_bytes())
+            .map(|_| ())
+    }
+
+    #[pyo3(signature = (message, yes_string, no_string=None))]
+    fn confirm(
+        &self,
+        py: Python<'_>,
+        message: &str,
+        yes_string: &str,
+        no_string: Option<&str>,
+    ) -> PyResult<Option<bool>> {
+        let metadata = std::iter::once(yes_string)
+            .chain(no_string)
+            .map(|s| BASE64_STANDARD_NO_PAD.encode(s))
+            .collect();
+
+        Ok(self
+            .send(py, CMD_CONFIRM, metadata, message.as_bytes())?
+            .ok()
+            .map(|response| response.args.first().is_some_and(|a| a == "yes")))
+    }
+
+    fn request_public_string(&self, py: Python<'_>, description: &str) -> PyResult<Option<String>> {
+        Ok(self
+            .send(py, CMD_REQUEST_PUBLIC, vec![], description.as_bytes())?
+            .ok()
+            .map(|response| String::from_utf8_lossy(&response.body).into_owned()))
+    }
+
+    fn request_passphrase(&self, py: Python<'_>, description: &str) -> PyResult<Option<String>> {
+        Ok(self
+            .send(py, CMD_REQUEST_SECRET, vec![], description.as_bytes())?
+            .ok()
+            .map(|response| String::from_utf8_lossy(&response.body).into_owned()))
+    }
+}
+
+fn single_arg(stanza: age_core::format::Stanza) -> Result<String, String> {
+    let tag = stanza.tag;
+    <[String; 1]>::try_from(stanza.args)
+        .map(|[arg]| arg)
+        .map_err(|_| format!("{tag} command must have exactly one metadata argument"))
+}
+
+// Runs the bidirectional phase of a state machine, in which the plugin sends
+// commands and the client responds.
+//
+// `steps` runs on this thread with the GIL held, so that it can call into
+// Python; a scoped thread owns the connection and relays the commands sent
+// through the `ClientCallbacks` handle.
+fn bidir_phase<R, W, F>(py: Python<'_>, conn: &mut Connection<R, W>, steps: F) -> PyResult<()>
+where
+    R: Read + Send,
+    W: Write + Send,
+    F: FnOnce(&Bound<'_, ClientCallbacks>) -> PyResult<()>,
+{
+    let (sender, commands) = mpsc::channel::<ClientCommand>();
+    let callbacks = Bound::new(py, ClientCallbacks(Mutex::new(Some(sender))))?;
+
+    std::thread::scope(|scope| {
+        let relay = scope.spawn(move || {
+            conn.bidir_send(|mut phase| {
+                for (command, metadata, data, reply) in commands {
+                    let metadata = metadata.iter().map(String::as_str).collect::<Vec<_>>();
+                    // The receiving end only goes away if the caller gave up waiting.
+                    let _ = reply.send(phase.send(&command, &metadata, &data));
+                }
+                Ok(())
+            })
+        });
+
+        let result = steps(&callbacks);
+        callbacks.get().close();
+        let relayed = relay.join().expect("plugin connection thread panicked");
+
+        result.and(relayed.map_err(PyErr::from))
+    })
+}
+
+// Sends a command whose response carries no information for the plugin.
+fn send_command(
+    py: Python<'_>,
+    callbacks: &ClientCallbacks,
+    command: &str,
+    metadata: Vec<String>,
+    data: &[u8],
+) -> PyResult<()> {
+    callbacks.send(py, command, metadata, data).map(|_| ())
+}
+
+fn send_error(
+    py: Python<'_>,
+    callbacks: &ClientCallbacks,
+    metadata: Vec<String>,
+    message: &str,
+) -> PyResult<()> {
+    send_command(py, callbacks, CMD_ERROR, metadata, message.as_bytes())
+}
+
+fn serve_recipient_v1(py: Python<'_>, recipient_impl: &Bound<'_, PyAny>) -> PyResult<()> {
+    let mut conn = Connection::accept();
+
+    // Phase 1: receive recipients, identities, and the file keys to wrap.
+    let (recipients, identities, file_keys, _) = py.allow_threads(|| {
+        conn.unidir_receive(
+            (CMD_ADD_RECIPIENT, single_arg),
+            (CMD_ADD_IDENTITY, single_arg),
+            (Some(CMD_WRAP_FILE_KEY), |s| Ok(s.body)),
+            (Some(CMD_EXTENSION_LABELS), |_| Ok(())),
         )
-        .map(Arc::new)
-        .map(Self)
-        .map_err(|err| DecryptError::new_err(err.to_string()))
+    })?;
+
+    // Phase 2: return either a set of stanzas for each file key, or errors.
+    bidir_phase(py, &mut conn, |callbacks| {
+        let (recipients, identities, file_keys) = match (recipients, identities, file_keys) {
+            (Ok(r), Ok(i), Some(Ok(f))) => (r, i, f),
+            (r, i, f) => {
+                for message in [r.err(), i.err(), f.and_then(Result::err)]
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                {
+                    send_error(py, callbacks.get(), vec!["internal".into()], &message)?;
+                }
+                return Ok(());
+            }
+        };
+
+        let mut errors = vec![];
+        let recipients = recipients
+            .iter()
+            .enumerate()
+            .filter_map(|(index, r)| match age::plugin::Recipient::from_str(r) {
+                Ok(r) => Some(Recipient(r)),
+                Err(e) => {
+                    errors.push((vec!["recipient".into(), index.to_string()], e));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let identities = identities
+            .iter()
+            .enumerate()
+            .filter_map(|(index, i)| match age::plugin::Identity::from_str(i) {
+                Ok(i) => Some(Identity(i)),
+                Err(e) => {
+                    errors.push((vec!["identity".into(), index.to_string()], e));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            for (metadata, message) in errors {
+                send_error(py, callbacks.get(), metadata, message)?;
+            }
+            return Ok(());
+        }
+
+        for (index, file_key) in file_keys.iter().enumerate() {
+            let stanzas = recipient_impl
+                .call_method1(
+                    pyo3::intern!(py, "wrap_file_key"),
+                    (
+                        PyBytes::new(py, file_key),
+                        recipients.clone(),
+                        identities.clone(),
+                        callbacks,
+                    ),
+                )
+                .and_then(|stanzas| stanzas.extract::<Vec<PyRef<'_, Stanza>>>());
+
+            match stanzas {
+                Ok(stanzas) => {
+                    for stanza in stanzas {
+                        let metadata = [index.to_string(), stanza.tag.clone()]
+                            .into_iter()
+                            .chain(stanza.args.iter().cloned())
+                            .collect();
+                        send_command(
+                            py,
+                            callbacks.get(),
+                            CMD_RECIPIENT_STANZA,
+                            metadata,
+                            &stanza.body,
+                        )?;
+                    }
+                }
+                Err(e) => {
+                    return send_error(py, callbacks.get(), vec!["internal".into()], &e.to_string())
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+fn serve_identity_v1(py: Python<'_>, identity_impl: &Bound<'_, PyAny>) -> PyResult<()> {
+    let mut conn = Connection::accept();
+
+    // Phase 1: receive identities, and the stanzas of each file to unwrap.
+    let (identities, stanzas, _, _) = py.allow_threads(|| {
+        conn.unidir_receive(
+            (CMD_ADD_IDENTITY, single_arg),
+            (CMD_RECIPIENT_STANZA, |s| {
+                let mut args = s.args.into_iter();
+                match (args.next().map(|i| i.parse::<usize>()), args.next()) {
+                    (Some(Ok(file_index)), Some(tag)) => Ok((
+                        file_index,
+                        Stanza {
+                            tag,
+                            args: args.collect(),
+                            body: s.body,
+                        },
+                    )),
+                    _ => Err(format!(
+                        "{CMD_RECIPIENT_STANZA} command must have a file index and a stanza tag"
+                    )),
+                }
+            }),
+            (None, |_| Ok(())),
+            (None, |_| Ok(())),
+        )
+    })?;
+
+    // Phase 2: return the file key for each file we can unwrap, or errors.
+    bidir_phase(py, &mut conn, |callbacks| {
+        let (identities, stanzas) = match (identities, stanzas) {
+            (Ok(i), Ok(s)) => (i, s),
+            (i, s) => {
+                for message in [i.err(), s.err()].into_iter().flatten().flatten() {
+                    send_error(py, callbacks.get(), vec!["internal".into()], &message)?;
+                }
+                return Ok(());
+            }
+        };
+
+        let mut errors = vec![];
+        let identities = identities
+            .iter()
+            .enumerate()
+            .filter_map(|(index, i)| match age::plugin::Identity::from_str(i) {
+                Ok(i) => Some(Identity(i)),
+                Err(e) => {
+                    errors.push((vec!["identity".into(), index.to_string()], e));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            for (metadata, message) in errors {
+                send_error(py, callbacks.get(), metadata, message)?;
+            }
+            return Ok(());
+        }
+
+        let mut files = BTreeMap::<usize, Vec<Stanza>>::new();
+        for (file_index, stanza) in stanzas {
+            files.entry(file_index).or_default().push(stanza);
+        }
+
+        for (file_index, stanzas) in files {
+            let file_key = identity_impl
+                .call_method1(
+                    pyo3::intern!(py, "unwrap_file_key"),
+                    (stanzas, identities.clone(), callbacks),
+                )
+                .and_then(|file_key| file_key.extract::<Option<Vec<u8>>>());
+
+            match file_key {
+                Ok(Some(file_key)) => {
+                    send_command(
+                        py,
+                        callbacks.get(),
+                        CMD_FILE_KEY,
+                        vec![file_index.to_string()],
+                        &file_key,
+                    )?;
+                }
+                Ok(None) => (),
+                Err(e) => send_error(py, callbacks.get(), vec!["internal".into()], &e.to_string())?,
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// Runs the plugin side of the age plugin protocol over stdin and stdout.
+///
+/// The state machine is taken from the `--age-plugin=` argument that age
+/// clients launch plugins with, unless given explicitly.
+#[pyfunction]
+#[pyo3(signature = (recipient_impl=None, identity_impl=None, state_machine=None))]
+fn serve(
+    py: Python<'_>,
+    recipient_impl: Option<Bound<'_, PyAny>>,
+    identity_impl: Option<Bound<'_, PyAny>>,
+    state_machine: Option<String>,
+) -> PyResult<()> {
+    let state_machine = match state_machine {
+        Some(state_machine) => state_machine,
+        None => py
+            .import("sys")?
+            .getattr("argv")?
+            .extract::<Vec<String>>()?
+            .into_iter()
+            .find_map(|arg| arg.strip_prefix("--age-plugin=").map(str::to_owned))
+            .ok_or_else(|| PyValueError::new_err("missing --age-plugin argument"))?,
+    };
+
+    match (state_machine.as_str(), recipient_impl, identity_impl) {
+        (RECIPIENT_V1, Some(recipient_impl), _) => serve_recipient_v1(py, &recipient_impl),
+        (IDENTITY_V1, _, Some(identity_impl)) => serve_identity_v1(py, &identity_impl),
+        (state_machine, _, _) => Err(PyValueError::new_err(format!(
+            "unsupported state machine: {state_machine}"
+        ))),
     }
 }
 
 pub(crate) fn module(py: Python<'_>) -> PyResult<Bound<'_, PyModule>> {
     let module = PyModule::new(py, "plugin")?;
+    module.gil_used(false)?;
 
     module.add_class::<Recipient>()?;
     module.add_class::<Identity>()?;
     module.add_class::<RecipientPluginV1>()?;
     module.add_class::<IdentityPluginV1>()?;
+    module.add_class::<Stanza>()?;
+    module.add_class::<ClientCallbacks>()?;
+    module.add_wrapped(wrap_pyfunction!(serve))?;
 
     Ok(module)
 }
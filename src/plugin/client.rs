@@ -0,0 +1,664 @@
+//! The client side of the age plugin protocol.
+//!
+//! `age` implements this too, but its implementation always resolves plugins
+//! through `$PATH` and hands them the entire environment. This is a
+//! reimplementation that lets the caller control both, and bound the time
+//! spent waiting on a plugin.
+//!
+//! `age_core::plugin::Connection` can only be opened by spawning the plugin
+//! itself, and never exposes the child process, so the connection is
+//! reimplemented here too; the wire format, and the `AGEDEBUG=plugin` tee,
+//! come from `age_core`.
+
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use age::{secrecy::ExposeSecret, Callbacks, DecryptError, EncryptError};
+use age_core::{
+    format::{read, write, FileKey, Stanza},
+    io::{DebugReader, DebugWriter},
+    plugin::{IDENTITY_V1, RECIPIENT_V1},
+};
+use base64::{prelude::BASE64_STANDARD_NO_PAD, Engine};
+use io_tee::{ReadExt, WriteExt};
+
+const CMD_DONE: &str = "done";
+const CMD_OK: &str = "ok";
+const CMD_FAIL: &str = "fail";
+const CMD_UNSUPPORTED: &str = "unsupported";
+const CMD_ERROR: &str = "error";
+const CMD_RECIPIENT_STANZA: &str = "recipient-stanza";
+const CMD_LABELS: &str = "labels";
+const CMD_MSG: &str = "msg";
+const CMD_CONFIRM: &str = "confirm";
+const CMD_REQUEST_PUBLIC: &str = "request-public";
+const CMD_REQUEST_SECRET: &str = "request-secret";
+const CMD_FILE_KEY: &str = "file-key";
+
+const SLOW_PLUGIN: Duration = Duration::from_secs(10);
+
+pub(crate) fn valid_plugin_name(plugin_name: &str) -> bool {
+    plugin_name
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() | matches!(b, b'+' | b'-' | b'.' | b'_'))
+        && !plugin_name.is_empty()
+}
+
+fn binary_name(plugin_name: &str) -> String {
+    format!("age-plugin-{}", plugin_name)
+}
+
+/// How to launch a plugin binary.
+pub(crate) struct Plugin {
+    binary_name: String,
+    path: PathBuf,
+    env: Option<HashMap<OsString, OsString>>,
+    timeout: Option<Duration>,
+}
+
+impl Plugin {
+    /// Locates the plugin with the given name.
+    ///
+    /// An explicit `path` is used as-is. Otherwise, the binary is looked up
+    /// in the `PATH` of `env` if one is given, or of this process if not.
+    ///
+    /// On error, returns the binary name that could not be located.
+    pub(crate) fn new(
+        name: &str,
+        path: Option<PathBuf>,
+        env: Option<HashMap<OsString, OsString>>,
+        timeout: Option<Duration>,
+    ) -> Result<Self, String> {
+        if !valid_plugin_name(name) {
+            return Err(name.to_owned());
+        }
+
+        let binary_name = binary_name(name);
+        let path = match (path, &env) {
+            (Some(path), _) => Ok(path),
+            (None, Some(env)) => which::which_in(
+                &binary_name,
+                env.get(&OsString::from("PATH")),
+                std::env::current_dir().map_err(|_| binary_name.clone())?,
+            ),
+            (None, None) => which::which(&binary_name),
+        }
+        .map_err(|_| binary_name.clone())?;
+
+        Ok(Self {
+            binary_name,
+            path,
+            env,
+            timeout,
+        })
+    }
+
+    fn connect(&self, state_machine: &str) -> io::Result<Connection> {
+        let working_dir = tempfile::tempdir()?;
+        let debug_enabled = std::env::var("AGEDEBUG")
+            .map(|s| s == "plugin")
+            .unwrap_or(false);
+
+        let mut command = Command::new(&self.path);
+        if let Some(env) = &self.env {
+            command.env_clear().envs(env);
+        }
+        let mut child = command
+            .arg(format!("--age-plugin={}", state_machine))
+            .current_dir(working_dir.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(if debug_enabled {
+                Stdio::inherit()
+            } else {
+                Stdio::null()
+            })
+            .spawn()?;
+
+        // As in `age`, AGEDEBUG=plugin also prints the messages exchanged
+        // with the plugin.
+        let input = child.stdout.take().expect("could open stdout");
+        let output = child.stdin.take().expect("could open stdin");
+        let (input, output) = match debug_enabled {
+            true => (
+                DebugReader::On(input.tee_dbg()),
+                DebugWriter::On(output.tee_dbg()),
+            ),
+            false => (DebugReader::Off(input), DebugWriter::Off(output)),
+        };
+        let input = BufReader::new(input);
+        let child = Arc::new(Mutex::new(child));
+        let watchdog = self
+            .timeout
+            .map(|timeout| Watchdog::new(child.clone(), timeout));
+
+        Ok(Connection {
+            binary_name: self.binary_name.clone(),
+            input,
+            output,
+            buffer: String::new(),
+            watchdog,
+            child,
+            _working_dir: working_dir,
+        })
+    }
+}
+
+/// Kills a plugin that is still running once its timeout has elapsed. The
+/// clock stops while the watchdog is paused.
+///
+/// Dropping the watchdog cancels it.
+struct Watchdog {
+    // Sends whether the watchdog should be paused.
+    paused: mpsc::Sender<bool>,
+    fired: Arc<AtomicBool>,
+    timeout: Duration,
+}
+
+impl Watchdog {
+    fn new(child: Arc<Mutex<Child>>, timeout: Duration) -> Self {
+        let (paused, pauses) = mpsc::channel::<bool>();
+        let fired = Arc::new(AtomicBool::new(false));
+
+        let flag = fired.clone();
+        thread::spawn(move || {
+            let mut remaining = timeout;
+            let mut paused = false;
+            loop {
+                let started = Instant::now();
+                let signal = match paused {
+                    true => pauses
+                        .recv()
+                        .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                    false => pauses.recv_timeout(remaining),
+                };
+                if !paused {
+                    remaining = remaining.saturating_sub(started.elapsed());
+                }
+
+                match signal {
+                    Ok(pause) => paused = pause,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        flag.store(true, Ordering::SeqCst);
+                        // Killing the plugin closes its end of the pipes, which
+                        // unblocks whatever read or write is waiting on it.
+                        let _ = child.lock().unwrap().kill();
+                        return;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+
+        Self {
+            paused,
+            fired,
+            timeout,
+        }
+    }
+
+    /// Pauses the watchdog until the returned guard is dropped.
+    fn pause(&self) -> PausedWatchdog<'_> {
+        let _ = self.paused.send(true);
+        PausedWatchdog(&self.paused)
+    }
+}
+
+struct PausedWatchdog<'a>(&'a mpsc::Sender<bool>);
+
+impl Drop for PausedWatchdog<'_> {
+    fn drop(&mut self) {
+        let _ = self.0.send(false);
+    }
+}
+
+/// Prints a message through the callbacks if the plugin hasn't finished after
+/// a while, like `age` does.
+///
+/// Dropping the guard cancels it.
+struct SlowPluginGuard {
+    _cancel: mpsc::Sender<()>,
+}
+
+impl SlowPluginGuard {
+    fn new<C: Callbacks>(callbacks: C, binary_name: String) -> Self {
+        let (cancel, cancelled) = mpsc::channel::<()>();
+
+        thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = cancelled.recv_timeout(SLOW_PLUGIN) {
+                callbacks.display_message(&format!("Waiting for {}...", binary_name));
+            }
+        });
+
+        Self { _cancel: cancel }
+    }
+}
+
+/// A connection to a running plugin binary.
+struct Connection {
+    binary_name: String,
+    input: BufReader<DebugReader<ChildStdout>>,
+    output: DebugWriter<ChildStdin>,
+    buffer: String,
+    watchdog: Option<Watchdog>,
+    child: Arc<Mutex<Child>>,
+    _working_dir: tempfile::TempDir,
+}
+
+impl Drop for Connection {
+    // A plugin that has answered everything it was asked has nothing left to
+    // do, so it's killed if it hasn't exited yet, and then reaped either way.
+    fn drop(&mut self) {
+        self.watchdog = None;
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        if let Ok(None) = child.try_wait() {
+            let _ = child.kill();
+        }
+        let _ = child.wait();
+    }
+}
+
+impl Connection {
+    fn send<S: AsRef<str>>(
+        &mut self,
+        command: &str,
+        metadata: &[S],
+        data: &[u8],
+    ) -> io::Result<()> {
+        let res = cookie_factory::gen_simple(
+            write::age_stanza(command, metadata, data),
+            &mut self.output,
+        )
+        .map_err(|e| match e {
+            cookie_factory::GenError::IoError(e) => e,
+            e => io::Error::other(e.to_string()),
+        })
+        .and_then(|w| w.flush());
+
+        self.handle_errors(res)
+    }
+
+    fn send_stanza(&mut self, command: &str, metadata: &[&str], stanza: &Stanza) -> io::Result<()> {
+        let metadata = metadata
+            .iter()
+            .copied()
+            .chain(iter::once(stanza.tag.as_str()))
+            .chain(stanza.args.iter().map(|s| s.as_str()))
+            .collect::<Vec<_>>();
+
+        self.send(command, &metadata, &stanza.body)
+    }
+
+    fn receive(&mut self) -> io::Result<Stanza> {
+        let res = loop {
+            match read::age_stanza(self.buffer.as_bytes()) {
+                Ok((remainder, stanza)) => {
+                    break Ok((Stanza::from(stanza), self.buffer.len() - remainder.len()))
+                }
+                Err(e) if e.is_incomplete() => match self.input.read_line(&mut self.buffer) {
+                    Ok(0) => {
+                        break Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "incomplete response",
+                        ))
+                    }
+                    Ok(_) => (),
+                    Err(e) => break Err(e),
+                },
+                Err(_) => {
+                    break Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid response",
+                    ))
+                }
+            }
+        };
+
+        let (stanza, consumed) = self.handle_errors(res)?;
+        self.buffer = self.buffer.split_off(consumed);
+
+        Ok(stanza)
+    }
+
+    // Turns the errors caused by a dead plugin into something actionable.
+    fn handle_errors<T>(&self, res: io::Result<T>) -> io::Result<T> {
+        res.map_err(|e| match &self.watchdog {
+            Some(watchdog) if watchdog.fired.load(Ordering::SeqCst) => io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "{} did not finish within {:?}",
+                    self.binary_name, watchdog.timeout
+                ),
+            ),
+            _ => match e.kind() {
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe => io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    format!("{} unexpectedly died", self.binary_name),
+                ),
+                _ => e,
+            },
+        })
+    }
+
+    /// Runs the bidirectional phase of a state machine, in which the plugin
+    /// sends commands and we respond to them.
+    fn bidir_receive<H>(&mut self, commands: &[&str], mut handler: H) -> io::Result<()>
+    where
+        H: FnMut(Stanza, Reply<'_>) -> io::Result<()>,
+    {
+        loop {
+            let stanza = self.receive()?;
+            match stanza.tag.as_str() {
+                CMD_DONE => break Ok(()),
+                t if commands.contains(&t) => handler(stanza, Reply(self))?,
+                _ => self.send::<&str>(CMD_UNSUPPORTED, &[], &[])?,
+            }
+        }
+    }
+}
+
+/// The possible replies to a command from the plugin.
+struct Reply<'a>(&'a mut Connection);
+
+impl Reply<'_> {
+    /// Runs `f`, which waits on the user, without the time it takes counting
+    /// towards the plugin's timeout.
+    fn interact<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _paused = self.0.watchdog.as_ref().map(Watchdog::pause);
+        f()
+    }
+
+    fn ok(self, data: Option<&[u8]>) -> io::Result<()> {
+        self.0.send::<&str>(CMD_OK, &[], data.unwrap_or_default())
+    }
+
+    fn ok_with_metadata(self, metadata: &[&str]) -> io::Result<()> {
+        self.0.send(CMD_OK, metadata, &[])
+    }
+
+    fn fail(self) -> io::Result<()> {
+        self.0.send::<&str>(CMD_FAIL, &[], &[])
+    }
+}
+
+// `age` doesn't export its `PluginError` type, so errors are collected as
+// `error` stanzas, which it knows how to convert.
+fn error_stanza(args: Vec<String>, message: &[u8]) -> Stanza {
+    Stanza {
+        tag: CMD_ERROR.to_owned(),
+        args,
+        body: message.to_vec(),
+    }
+}
+
+fn other_error(message: String) -> Stanza {
+    error_stanza(vec!["internal".to_owned()], message.as_bytes())
+}
+
+// Handles the commands that both state machines use to interact with the user.
+fn handle_interaction<C: Callbacks>(
+    command: Stanza,
+    reply: Reply<'_>,
+    errors: &mut Vec<Stanza>,
+    callbacks: &C,
+) -> io::Result<()> {
+    let message = String::from_utf8_lossy(&command.body);
+    match command.tag.as_str() {
+        CMD_MSG => {
+            reply.interact(|| callbacks.display_message(&message));
+            reply.ok(None)
+        }
+        CMD_CONFIRM => {
+            let mut strings = command
+                .args
+                .iter()
+                .take(2)
+                .map(|s| BASE64_STANDARD_NO_PAD.decode(s));
+            let (yes_string, no_string) = match (strings.next(), strings.next()) {
+                (Some(Ok(yes_string)), None) => (yes_string, None),
+                (Some(Ok(yes_string)), Some(Ok(no_string))) => (yes_string, Some(no_string)),
+                _ => {
+                    errors.push(other_error(format!(
+                        "{} command must have one or two Base64-encoded metadata arguments",
+                        CMD_CONFIRM
+                    )));
+                    return reply.fail();
+                }
+            };
+            let no_string = no_string.as_ref().map(|s| String::from_utf8_lossy(s));
+            match reply.interact(|| {
+                callbacks.confirm(
+                    &message,
+                    &String::from_utf8_lossy(&yes_string),
+                    no_string.as_ref().map(|s| s.borrow()),
+                )
+            }) {
+                Some(value) => reply.ok_with_metadata(&[if value { "yes" } else { "no" }]),
+                None => reply.fail(),
+            }
+        }
+        CMD_REQUEST_PUBLIC => match reply.interact(|| callbacks.request_public_string(&message)) {
+            Some(value) => reply.ok(Some(value.as_bytes())),
+            None => reply.fail(),
+        },
+        CMD_REQUEST_SECRET => match reply.interact(|| callbacks.request_passphrase(&message)) {
+            Some(secret) => reply.ok(Some(secret.expose_secret().as_bytes())),
+            None => reply.fail(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// An age plugin with an associated set of recipients.
+pub(crate) struct RecipientPluginV1<C: Callbacks> {
+    pub(crate) plugin: Plugin,
+    pub(crate) recipients: Vec<age::plugin::Recipient>,
+    pub(crate) identities: Vec<age::plugin::Identity>,
+    pub(crate) callbacks: C,
+}
+
+impl<C: Callbacks> age::Recipient for RecipientPluginV1<C> {
+    fn wrap_file_key(
+        &self,
+        file_key: &FileKey,
+    ) -> Result<(Vec<Stanza>, HashSet<String>), EncryptError> {
+        let mut conn = self.plugin.connect(RECIPIENT_V1)?;
+        let _guard = SlowPluginGuard::new(self.callbacks.clone(), self.plugin.binary_name.clone());
+
+        // Phase 1: add recipients, identities, and file key to wrap
+        for recipient in &self.recipients {
+            conn.send("add-recipient", &[recipient.to_string()], &[])?;
+        }
+        for identity in &self.identities {
+            conn.send("add-identity", &[identity.to_string()], &[])?;
+        }
+        conn.send::<&str>("extension-labels", &[], &[])?;
+        conn.send::<&str>("wrap-file-key", &[], file_key.expose_secret())?;
+        conn.send::<&str>(CMD_DONE, &[], &[])?;
+
+        // Phase 2: collect either stanzas or errors
+        let mut stanzas = vec![];
+        let mut labels = None;
+        let mut errors = vec![];
+        conn.bidir_receive(
+            &[
+                CMD_MSG,
+                CMD_CONFIRM,
+                CMD_REQUEST_PUBLIC,
+                CMD_REQUEST_SECRET,
+                CMD_RECIPIENT_STANZA,
+                CMD_LABELS,
+                CMD_ERROR,
+            ],
+            |mut command, reply| match command.tag.as_str() {
+                CMD_RECIPIENT_STANZA => {
+                    // We only requested one file key be wrapped.
+                    if command.args.len() >= 2 && command.args[0] == "0" {
+                        command.args.remove(0);
+                        command.tag = command.args.remove(0);
+                        stanzas.push(command);
+                    } else {
+                        errors.push(other_error(format!(
+                            "{} command must reference the file key we provided",
+                            CMD_RECIPIENT_STANZA
+                        )));
+                    }
+                    reply.ok(None)
+                }
+                CMD_LABELS => {
+                    let labels_count = command.args.len();
+                    let label_set = command.args.into_iter().collect::<HashSet<_>>();
+                    if labels.is_some() || label_set.len() != labels_count {
+                        errors.push(other_error(format!(
+                            "{} command must be sent once, without duplicate labels",
+                            CMD_LABELS
+                        )));
+                    } else {
+                        labels = Some(label_set);
+                    }
+                    reply.ok(None)
+                }
+                CMD_ERROR => {
+                    let recipient = match command.args.as_slice() {
+                        [kind, index] if kind == "recipient" => index
+                            .parse()
+                            .ok()
+                            .and_then(|index: usize| self.recipients.get(index)),
+                        _ => None,
+                    };
+                    errors.push(match recipient {
+                        Some(r) => {
+                            error_stanza(vec!["recipient".to_owned(), r.to_string()], &command.body)
+                        }
+                        None => command,
+                    });
+                    reply.ok(None)
+                }
+                _ => handle_interaction(command, reply, &mut errors, &self.callbacks),
+            },
+        )?;
+
+        match (stanzas.is_empty(), errors.is_empty()) {
+            (false, true) => Ok((stanzas, labels.unwrap_or_default())),
+            (true, true) => Err(EncryptError::Plugin(vec![other_error(
+                "Plugin returned neither stanzas nor errors".to_owned(),
+            )
+            .into()])),
+            _ => Err(EncryptError::Plugin(
+                errors.into_iter().map(Into::into).collect(),
+            )),
+        }
+    }
+}
+
+/// An age plugin with an associated set of identities.
+pub(crate) struct IdentityPluginV1<C: Callbacks> {
+    pub(crate) plugin: Plugin,
+    pub(crate) identities: Vec<age::plugin::Identity>,
+    pub(crate) callbacks: C,
+}
+
+impl<C: Callbacks> IdentityPluginV1<C> {
+    fn unwrap_stanzas<'a>(
+        &self,
+        stanzas: impl Iterator<Item = &'a Stanza>,
+    ) -> Option<Result<FileKey, DecryptError>> {
+        // If the plugin doesn't know how to unwrap identities, skip it.
+        let mut conn = self.plugin.connect(IDENTITY_V1).ok()?;
+        let _guard = SlowPluginGuard::new(self.callbacks.clone(), self.plugin.binary_name.clone());
+
+        // Phase 1: add identities and stanzas
+        let res = self
+            .identities
+            .iter()
+            .try_for_each(|identity| conn.send("add-identity", &[identity.to_string()], &[]))
+            .and_then(|_| {
+                stanzas
+                    .into_iter()
+                    .try_for_each(|stanza| conn.send_stanza("recipient-stanza", &["0"], stanza))
+            })
+            .and_then(|_| conn.send::<&str>(CMD_DONE, &[], &[]));
+        if let Err(e) = res {
+            return Some(Err(e.into()));
+        }
+
+        // Phase 2: interactively unwrap
+        let mut file_key = None;
+        let mut errors = vec![];
+        if let Err(e) = conn.bidir_receive(
+            &[
+                CMD_MSG,
+                CMD_CONFIRM,
+                CMD_REQUEST_PUBLIC,
+                CMD_REQUEST_SECRET,
+                CMD_FILE_KEY,
+                CMD_ERROR,
+            ],
+            |command, reply| match command.tag.as_str() {
+                CMD_FILE_KEY => {
+                    // We only requested one file key be unwrapped.
+                    if command.args.len() == 1 && command.args[0] == "0" && file_key.is_none() {
+                        file_key = Some(FileKey::try_init_with_mut(|file_key| {
+                            if command.body.len() == file_key.len() {
+                                file_key.copy_from_slice(&command.body);
+                                Ok(())
+                            } else {
+                                Err(DecryptError::DecryptionFailed)
+                            }
+                        }));
+                        reply.ok(None)
+                    } else {
+                        reply.fail()
+                    }
+                }
+                CMD_ERROR => {
+                    let identity = match command.args.as_slice() {
+                        [kind, index] if kind == "identity" => index
+                            .parse()
+                            .ok()
+                            .and_then(|index: usize| self.identities.get(index)),
+                        _ => None,
+                    };
+                    errors.push(match identity {
+                        Some(i) => error_stanza(
+                            vec!["identity".to_owned(), binary_name(i.plugin())],
+                            &command.body,
+                        ),
+                        None => command,
+                    });
+                    reply.ok(None)
+                }
+                _ => handle_interaction(command, reply, &mut errors, &self.callbacks),
+            },
+        ) {
+            return Some(Err(e.into()));
+        }
+
+        if file_key.is_none() && !errors.is_empty() {
+            Some(Err(DecryptError::Plugin(
+                errors.into_iter().map(Into::into).collect(),
+            )))
+        } else {
+            file_key
+        }
+    }
+}
+
+impl<C: Callbacks> age::Identity for IdentityPluginV1<C> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, DecryptError>> {
+        self.unwrap_stanzas(iter::once(stanza))
+    }
+
+    fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, DecryptError>> {
+        self.unwrap_stanzas(stanzas.iter())
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    Decryptor, Encryptor, Identity, Recipient,
+};
+use pyo3::prelude::*;
+
+use crate::{
+    armor::InputFormat,
+    copy::{self, CancellationToken, CountingReader, Progress, DEFAULT_PROGRESS_INTERVAL},
+    decryptor_error, encryptor_error, from_pyobject,
+    info::Tracker,
+    keys::{Identities, Recipients},
+    limits::Limits,
+    EncryptError, FileArg, PyrageRecipient,
+};
+
+/// Decrypts `reader` and encrypts it again into `output` under a fresh file
+/// key, one chunk at a time, without the plaintext leaving Rust.
+#[allow(clippy::too_many_arguments)]
+fn reencrypt_stream<R: BufRead + Send, W: Write + Send>(
+    py: Python<'_>,
+    identities: &Identities,
+    recipients: &[Box<dyn PyrageRecipient>],
+    reader: R,
+    output: W,
+    armored: bool,
+    limits: Limits,
+    progress: Option<Progress<'_>>,
+    cancel: Option<&CancellationToken>,
+) -> PyResult<W> {
+    let tracker = Tracker::new(identities);
+    let recipients = recipients.iter().map(|r| r.as_ref() as &dyn Recipient);
+
+    // The header is unwrapped before anything is written, so a file that
+    // none of `identities` can open produces no output at all.
+    let decryptor = Decryptor::new_buffered(limits.header(ArmoredReader::new(reader)))
+        .map_err(decryptor_error)?;
+    let mut plaintext = limits.plaintext(
+        decryptor
+            .decrypt(std::iter::once(&tracker as &dyn Identity))
+            .map_err(|e| tracker.error(e))?,
+    );
+
+    let encryptor = Encryptor::with_recipients(recipients).map_err(encryptor_error)?;
+    let format = match armored {
+        true => Format::AsciiArmor,
+        false => Format::Binary,
+    };
+    let mut output = ArmoredWriter::wrap_output(output, format)?;
+    let mut writer = encryptor
+        .wrap_output(&mut output)
+        .map_err(|e| EncryptError::new_err(e.to_string()))?;
+
+    // Payload errors on the way in surface as `OSError`, as in `decrypt_file`,
+    // and an exceeded limit as `LimitExceededError`.
+    copy::copy(
+        py,
+        &mut plaintext,
+        &mut writer,
+        progress,
+        cancel,
+        PyErr::from,
+    )?;
+
+    writer
+        .finish()
+        .map_err(|e| EncryptError::new_err(e.to_string()))?;
+    output
+        .finish()
+        .map_err(|e| EncryptError::new_err(e.to_string()))
+}
+
+#[pyfunction]
+#[pyo3(signature = (infile, outfile, identities, recipients, armored=false, *, format=InputFormat::Auto, mode=0o600, overwrite=true, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL, cancel=None, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn reencrypt_file(
+    py: Python<'_>,
+    infile: FileArg,
+    outfile: FileArg,
+    identities: Identities,
+    recipients: Recipients,
+    armored: bool,
+    format: InputFormat,
+    mode: u32,
+    overwrite: bool,
+    progress: Option<Bound<'_, PyAny>>,
+    progress_interval: u64,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<()> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+
+    let total = infile.size();
+    let reader = CountingReader::new(infile.open(py)?);
+    let progress = Progress::new(progress, progress_interval, total, reader.count());
+    let reader = format.check_reader(BufReader::new(reader))?;
+
+    // The output is only moved into place once it is complete, so `infile`
+    // and `outfile` may be the same path.
+    let output = outfile.create(py, mode, overwrite)?;
+    let mut output = BufWriter::new(output);
+
+    reencrypt_stream(
+        py,
+        &identities,
+        &recipients,
+        reader,
+        &mut output,
+        armored,
+        limits,
+        progress,
+        cancel.as_deref(),
+    )?;
+
+    output.into_inner().map_err(|e| e.into_error())?.finish()?;
+
+    Ok(())
+}
+
+#[pyfunction]
+#[pyo3(signature = (reader, writer, identities, recipients, armored=false, *, format=InputFormat::Auto, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL, cancel=None, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn reencrypt_io(
+    py: Python<'_>,
+    reader: PyObject,
+    writer: PyObject,
+    identities: Identities,
+    recipients: Recipients,
+    armored: bool,
+    format: InputFormat,
+    progress: Option<Bound<'_, PyAny>>,
+    progress_interval: u64,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<()> {
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+
+    let reader = CountingReader::new(from_pyobject(reader, true)?);
+    let progress = Progress::new(progress, progress_interval, None, reader.count());
+    let writer = from_pyobject(writer, false)?;
+    let reader = format.check_reader(BufReader::new(reader))?;
+    let mut writer = BufWriter::new(writer);
+
+    reencrypt_stream(
+        py,
+        &identities,
+        &recipients,
+        reader,
+        &mut writer,
+        armored,
+        limits,
+        progress,
+        cancel.as_deref(),
+    )?
+    .flush()?;
+
+    Ok(())
+}
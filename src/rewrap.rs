@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+use std::io::{BufReader, BufWriter, Write};
+
+use age::{
+    armor::{ArmoredReader, ArmoredWriter, Format},
+    scrypt,
+    secrecy::ExposeSecret,
+    Identity,
+};
+use age_core::{
+    format::{grease_the_joint, read::legacy_age_stanza, write, FileKey, Stanza},
+    primitives::hkdf,
+};
+use base64::{prelude::BASE64_STANDARD_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use pyo3::{exceptions::PyValueError, prelude::*, pybacked::PyBackedStr};
+use sha2::Sha256;
+
+use crate::{
+    armor::{self, InputFormat},
+    copy, encryptor_error,
+    file::FileArg,
+    info::Tracker,
+    keys::{Identities, Recipients},
+    limits::{self, Limits},
+    secret::Passphrase,
+    stream, DecryptError, PyrageIdentity, PyrageRecipient, RageDecryptError, RageEncryptError,
+};
+
+const HEADER_V1_LINE: &[u8] = b"age-encryption.org/v1\n";
+const MAC_TAG: &[u8] = b"---";
+const HEADER_KEY_LABEL: &[u8] = b"header";
+const SCRYPT_TAG: &str = "scrypt";
+const GREASE_SUFFIX: &str = "-grease";
+const POSTQUANTUM_TAG: &str = "mlkem768x25519";
+const POSTQUANTUM_LABEL: &str = "postquantum";
+// The header ends with "--- ", a 43-character Base64 MAC, and a newline.
+const MAC_LINE_LEN: usize = MAC_TAG.len() + 1 + 43 + 1;
+const NONCE_SIZE: usize = 16;
+
+/// The `remove` argument to `rewrap`: either `"all"`, or identities whose
+/// stanzas should be dropped from the header.
+pub(crate) enum Remove {
+    All,
+    Identities(Vec<Box<dyn PyrageIdentity>>),
+}
+
+impl<'source> FromPyObject<'source> for Remove {
+    fn extract_bound(ob: &Bound<'source, PyAny>) -> PyResult<Self> {
+        match ob.extract::<PyBackedStr>() {
+            Ok(s) if &*s == "all" => Ok(Self::All),
+            Ok(s) => Err(PyValueError::new_err(format!(
+                "invalid remove: {:?} (expected 'all' or a list of identities)",
+                &*s
+            ))),
+            Err(_) => Ok(Self::Identities(ob.extract()?)),
+        }
+    }
+}
+
+impl Remove {
+    fn removes(&self, stanza: &Stanza) -> bool {
+        match self {
+            Self::All => true,
+            Self::Identities(identities) => identities
+                .iter()
+                .any(|i| matches!(i.as_identity().unwrap_stanza(stanza), Some(Ok(_)))),
+        }
+    }
+}
+
+// `age` has already parsed and authenticated this header by the time we get
+// here, so this only fails on headers that `age` itself would reject.
+fn parse_stanzas(header: &[u8]) -> PyResult<Vec<Stanza>> {
+    let invalid = || DecryptError::new_err("Header is invalid");
+
+    let stanzas = header
+        .strip_prefix(HEADER_V1_LINE)
+        .and_then(|h| h.get(..h.len().checked_sub(MAC_LINE_LEN)?))
+        .ok_or_else(invalid)?;
+
+    // The stanza parser needs to see what follows the last stanza to know
+    // where it ends, so keep the MAC tag in the input.
+    let input = [stanzas, MAC_TAG].concat();
+    let mut rest = &input[..];
+    let mut parsed = vec![];
+    while rest != MAC_TAG {
+        let (remaining, stanza) = legacy_age_stanza(rest).map_err(|_| invalid())?;
+        parsed.push(Stanza::from(stanza));
+        rest = remaining;
+    }
+
+    Ok(parsed)
+}
+
+fn encode_header(stanzas: &[Stanza], file_key: &FileKey) -> Vec<u8> {
+    let mut header = HEADER_V1_LINE.to_vec();
+    for stanza in stanzas {
+        header = cookie_factory::gen_simple(
+            write::age_stanza(&stanza.tag, &stanza.args, &stanza.body),
+            header,
+        )
+        .expect("can serialize a stanza into a Vec");
+    }
+    header.extend_from_slice(MAC_TAG);
+
+    let mac_key = hkdf(&[], HEADER_KEY_LABEL, file_key.expose_secret());
+    let mut mac = Hmac::<Sha256>::new_from_slice(&mac_key).expect("key is the correct length");
+    mac.update(&header);
+
+    header.push(b' ');
+    header.extend_from_slice(
+        BASE64_STANDARD_NO_PAD
+            .encode(mac.finalize().into_bytes())
+            .as_bytes(),
+    );
+    header.push(b'\n');
+    header
+}
+
+fn is_grease(stanza: &Stanza) -> bool {
+    stanza.tag.ends_with(GREASE_SUFFIX)
+}
+
+/// The labels that the recipient behind `stanza` wrapped it with, for the
+/// stanza types where the tag alone says what they were. Plugin stanzas
+/// don't, and scrypt stanzas are checked separately.
+fn stanza_labels(stanza: &Stanza) -> Option<HashSet<String>> {
+    match stanza.tag.as_str() {
+        POSTQUANTUM_TAG => Some(HashSet::from([POSTQUANTUM_LABEL.to_owned()])),
+        "X25519" | "ssh-ed25519" | "ssh-rsa" => Some(HashSet::new()),
+        _ => None,
+    }
+}
+
+/// Wraps `file_key` to each of `recipients`, with the same checks that
+/// `age::Encryptor` applies to a set of recipients, counting the recipients
+/// of the `kept` stanzas among them.
+fn wrap_file_key(
+    file_key: &FileKey,
+    kept: &[Stanza],
+    recipients: &[Box<dyn PyrageRecipient>],
+) -> PyResult<Vec<Stanza>> {
+    let mut control = None;
+    let mut check = |r_labels: HashSet<String>| match control.take() {
+        Some(l_labels) if l_labels != r_labels => {
+            Err(encryptor_error(RageEncryptError::IncompatibleRecipients {
+                l_labels,
+                r_labels,
+            }))
+        }
+        _ => {
+            control = Some(r_labels);
+            Ok(())
+        }
+    };
+
+    for labels in kept.iter().filter_map(stanza_labels) {
+        check(labels)?;
+    }
+
+    let mut stanzas = vec![];
+    for recipient in recipients {
+        let (mut r_stanzas, r_labels) =
+            recipient.wrap_file_key(file_key).map_err(encryptor_error)?;
+        check(r_labels)?;
+        stanzas.append(&mut r_stanzas);
+    }
+
+    Ok(stanzas)
+}
+
+#[pyfunction]
+#[pyo3(signature = (infile, outfile, identities, add_recipients=Recipients::default(), remove=None, *, format=InputFormat::Auto, passphrase=None, mode=0o600, overwrite=true, max_header_size=None, max_stanzas=None, max_work_factor=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn rewrap(
+    py: Python<'_>,
+    infile: FileArg,
+    outfile: FileArg,
+    identities: Identities,
+    add_recipients: Recipients,
+    remove: Option<Remove>,
+    format: InputFormat,
+    passphrase: Option<Passphrase<'_>>,
+    mode: u32,
+    overwrite: bool,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+    max_work_factor: Option<u8>,
+) -> PyResult<()> {
+    // The payload is copied without being decrypted, so there's no
+    // plaintext to limit.
+    let limits = Limits::new(None, max_header_size, max_stanzas);
+
+    // Passphrase-encrypted files are unwrapped with the passphrase instead.
+    let scrypt = passphrase.map(|p| {
+        let mut identity = scrypt::Identity::new(p.secret());
+        if let Some(max_work_factor) = max_work_factor {
+            identity.set_max_work_factor(max_work_factor);
+        }
+        identity
+    });
+    let tracker = Tracker::new(&identities);
+    let identities = std::iter::once(&tracker as &dyn Identity)
+        .chain(scrypt.as_ref().map(|i| i as &dyn Identity))
+        .collect::<Vec<_>>();
+
+    let reader = format.check_reader(BufReader::new(infile.open(py)?))?;
+    // The output is armored if the input is.
+    let format = match armor::is_armored(armor::peeked(&reader)) {
+        true => Format::AsciiArmor,
+        false => Format::Binary,
+    };
+    let mut reader = limits.header(ArmoredReader::new(reader));
+
+    let (file_key, header) =
+        stream::read_header(&mut reader, &identities).map_err(|e| match e {
+            e @ RageDecryptError::ExcessiveWork { .. } => {
+                limits::excessive_work(e, max_work_factor)
+            }
+            e => tracker.error(e),
+        })?;
+    let (header, nonce) = header.split_at(header.len() - NONCE_SIZE);
+
+    let mut stanzas = parse_stanzas(header)?;
+    if let Some(remove) = &remove {
+        stanzas.retain(|stanza| !remove.removes(stanza));
+    }
+    let added = wrap_file_key(&file_key, &stanzas, &add_recipients)?;
+    stanzas.extend(added);
+
+    // Grease stanzas aren't for anyone, so a header of only grease can't be
+    // opened.
+    if stanzas.iter().all(is_grease) {
+        return Err(encryptor_error(RageEncryptError::MissingRecipients));
+    }
+    if stanzas.len() > 1 && stanzas.iter().any(|s| s.tag == SCRYPT_TAG) {
+        return Err(encryptor_error(
+            RageEncryptError::MixedRecipientAndPassphrase,
+        ));
+    }
+    // A header built from scratch gets its grease, as `age` would give it.
+    if matches!(remove, Some(Remove::All)) && stanzas.iter().all(|s| s.tag != SCRYPT_TAG) {
+        stanzas.push(grease_the_joint());
+    }
+
+    let output = outfile.create(py, mode, overwrite)?;
+    let mut output = BufWriter::new(output);
+    let mut writer = ArmoredWriter::wrap_output(&mut output, format)?;
+
+    writer.write_all(&encode_header(&stanzas, &file_key))?;
+    writer.write_all(nonce)?;
+    copy::copy(py, &mut reader, &mut writer, None, None, PyErr::from)?;
+    writer.finish()?;
+
+    output.into_inner().map_err(|e| e.into_error())?.finish()?;
+
+    Ok(())
+}
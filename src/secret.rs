@@ -0,0 +1,192 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use age::secrecy::SecretString;
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    pybacked::PyBackedStr,
+    types::{PyByteArray, PyBytes, PySlice},
+};
+use zeroize::Zeroizing;
+
+/// A passphrase, given as a `str` or a `bytearray` of UTF-8.
+///
+/// A `bytearray` is overwritten with zeros once the passphrase is dropped,
+/// i.e. when the call that took it returns. A `str` is immutable, so pyrage
+/// can only wipe its own copy.
+pub(crate) struct Passphrase<'py> {
+    value: Zeroizing<String>,
+    source: Option<Bound<'py, PyByteArray>>,
+}
+
+impl<'py> FromPyObject<'py> for Passphrase<'py> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(source) = ob.downcast::<PyByteArray>() {
+            let bytes = Zeroizing::new(source.to_vec());
+            let value = std::str::from_utf8(&bytes)
+                .map_err(|_| PyValueError::new_err("passphrase must be valid UTF-8"))?;
+
+            Ok(Self {
+                value: Zeroizing::new(value.to_owned()),
+                source: Some(source.clone()),
+            })
+        } else if let Ok(value) = ob.extract::<PyBackedStr>() {
+            Ok(Self {
+                value: Zeroizing::new((*value).to_owned()),
+                source: None,
+            })
+        } else {
+            Err(PyTypeError::new_err(
+                "invalid type (expected a str or bytearray passphrase)",
+            ))
+        }
+    }
+}
+
+impl Passphrase<'_> {
+    pub(crate) fn secret(&self) -> SecretString {
+        self.value.as_str().into()
+    }
+}
+
+impl Drop for Passphrase<'_> {
+    fn drop(&mut self) {
+        let Some(source) = &self.source else {
+            return;
+        };
+
+        // Assigning a slice of the same length overwrites the bytearray's
+        // buffer in place.
+        let py = source.py();
+        let zeros = PyBytes::new(py, &vec![0; source.len()]);
+        if let Err(e) = source.set_item(PySlice::full(py), zeros) {
+            e.write_unraisable(py, Some(source.as_any()));
+        }
+    }
+}
+
+/// How `decrypt` returns plaintext: `"bytes"` (the default), `"bytearray"`,
+/// or `"secret"` for a `SecretBuffer`.
+#[derive(Clone, Copy, Default)]
+pub(crate) enum OutputType {
+    #[default]
+    Bytes,
+    ByteArray,
+    Secret,
+}
+
+impl<'source> FromPyObject<'source> for OutputType {
+    fn extract_bound(ob: &Bound<'source, PyAny>) -> PyResult<Self> {
+        match &*ob.extract::<PyBackedStr>()? {
+            "bytes" => Ok(Self::Bytes),
+            "bytearray" => Ok(Self::ByteArray),
+            "secret" => Ok(Self::Secret),
+            other => Err(PyValueError::new_err(format!(
+                "invalid output_type: {other:?} (expected 'bytes', 'bytearray' or 'secret')"
+            ))),
+        }
+    }
+}
+
+impl OutputType {
+    /// Hands `plaintext` to Python. pyrage's own copy is wiped when it's
+    /// dropped, unless it's moved into a `SecretBuffer`.
+    pub(crate) fn wrap<'p>(
+        self,
+        py: Python<'p>,
+        plaintext: Zeroizing<Vec<u8>>,
+    ) -> PyResult<Bound<'p, PyAny>> {
+        match self {
+            Self::Bytes => Ok(PyBytes::new(py, &plaintext).into_any()),
+            Self::ByteArray => Ok(PyByteArray::new(py, &plaintext).into_any()),
+            Self::Secret => Ok(Bound::new(py, SecretBuffer::new(plaintext))?.into_any()),
+        }
+    }
+}
+
+/// Plaintext held by pyrage, which is wiped from memory on `close()` or when
+/// the buffer is garbage collected.
+#[pyclass(module = "pyrage", frozen)]
+pub(crate) struct SecretBuffer(Mutex<Option<Zeroizing<Vec<u8>>>>);
+
+impl SecretBuffer {
+    fn new(plaintext: Zeroizing<Vec<u8>>) -> Self {
+        Self(Mutex::new(Some(plaintext)))
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&[u8]) -> T) -> PyResult<T> {
+        match &*self.0.lock().unwrap() {
+            Some(plaintext) => Ok(f(plaintext)),
+            None => Err(PyValueError::new_err("SecretBuffer is closed")),
+        }
+    }
+}
+
+#[pymethods]
+impl SecretBuffer {
+    /// Copies the plaintext into a new `bytearray`, which the caller is then
+    /// responsible for wiping.
+    fn reveal<'p>(&self, py: Python<'p>) -> PyResult<Bound<'p, PyByteArray>> {
+        self.with(|plaintext| PyByteArray::new(py, plaintext))
+    }
+
+    fn close(&self) {
+        // Dropping the `Zeroizing` wipes it.
+        self.0.lock().unwrap().take();
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.0.lock().unwrap().is_none()
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        self.with(|plaintext| plaintext.len())
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(&self, _exc_type: PyObject, _exc_value: PyObject, _traceback: PyObject) -> bool {
+        self.close();
+        false
+    }
+
+    fn __repr__(&self) -> String {
+        match self.with(|plaintext| plaintext.len()) {
+            Ok(len) => format!("SecretBuffer(<{len} bytes>)"),
+            Err(_) => "SecretBuffer(<closed>)".into(),
+        }
+    }
+}
+
+/// Plaintext buffered in memory, which is wiped when it's dropped. It grows
+/// by copying into a new allocation and wiping the old one, so that growing
+/// it doesn't leave stray copies of the plaintext behind.
+#[derive(Default)]
+pub(crate) struct SecretVec(Zeroizing<Vec<u8>>);
+
+impl SecretVec {
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Write for SecretVec {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let needed = self.0.len() + buf.len();
+        if needed > self.0.capacity() {
+            let mut grown = Zeroizing::new(Vec::with_capacity(needed.max(self.0.capacity() * 2)));
+            grown.extend_from_slice(&self.0);
+            self.0 = grown;
+        }
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
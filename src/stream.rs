@@ -0,0 +1,445 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::thread;
+
+use age::{secrecy::ExposeSecret, Decryptor, Encryptor, Identity, Recipient};
+use age_core::{
+    format::{FileKey, Stanza},
+    primitives::hkdf,
+};
+use chacha20poly1305::{
+    aead::{AeadInPlace, KeyInit},
+    ChaCha20Poly1305, Nonce, Tag,
+};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    copy::{self, CancellationToken, Progress},
+    encryptor_error,
+    info::Tracker,
+    EncryptError, RageDecryptError, RageEncryptError,
+};
+
+// age's STREAM parameters; see the "Payload" section of the age spec.
+pub(crate) const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+const NONCE_SIZE: usize = 16;
+const PAYLOAD_KEY_LABEL: &[u8] = b"payload";
+
+// Each batch gives every thread this many chunks (1 MiB) to work on.
+const CHUNKS_PER_THREAD: usize = 16;
+
+/// Validates a `threads` argument.
+pub(crate) fn threads(threads: usize) -> PyResult<usize> {
+    match threads {
+        0 => Err(PyValueError::new_err("threads must be at least 1")),
+        n => Ok(n),
+    }
+}
+
+fn copy_file_key(file_key: &FileKey) -> FileKey {
+    FileKey::new(Box::new(*file_key.expose_secret()))
+}
+
+// The parallel paths let `age` produce and check headers as usual, and only
+// take over for the payload. To do that, they need the file key, which these
+// wrappers hold on to as it goes by...
+struct CaptureRecipient<'a> {
+    inner: &'a dyn Recipient,
+    file_key: &'a RefCell<Option<FileKey>>,
+}
+
+impl Recipient for CaptureRecipient<'_> {
+    fn wrap_file_key(
+        &self,
+        file_key: &FileKey,
+    ) -> Result<(Vec<Stanza>, std::collections::HashSet<String>), RageEncryptError> {
+        self.file_key
+            .borrow_mut()
+            .get_or_insert_with(|| copy_file_key(file_key));
+        self.inner.wrap_file_key(file_key)
+    }
+}
+
+struct CaptureIdentity<'a> {
+    identities: &'a [&'a dyn Identity],
+    file_key: RefCell<Option<FileKey>>,
+}
+
+impl CaptureIdentity<'_> {
+    fn capture(
+        &self,
+        result: Option<Result<FileKey, RageDecryptError>>,
+    ) -> Option<Result<FileKey, RageDecryptError>> {
+        if let Some(Ok(file_key)) = &result {
+            *self.file_key.borrow_mut() = Some(copy_file_key(file_key));
+        }
+        result
+    }
+}
+
+impl Identity for CaptureIdentity<'_> {
+    fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, RageDecryptError>> {
+        self.capture(self.identities.iter().find_map(|i| i.unwrap_stanza(stanza)))
+    }
+
+    fn unwrap_stanzas(&self, stanzas: &[Stanza]) -> Option<Result<FileKey, RageDecryptError>> {
+        self.capture(
+            self.identities
+                .iter()
+                .find_map(|i| i.unwrap_stanzas(stanzas)),
+        )
+    }
+}
+
+// ...and the header and payload nonce, which are everything `age` reads or
+// writes before the payload itself.
+struct Record<T> {
+    inner: T,
+    bytes: Vec<u8>,
+}
+
+impl<T> Record<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            bytes: vec![],
+        }
+    }
+}
+
+impl<R: Read> Read for Record<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Record<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn payload_key(file_key: &FileKey, header_and_nonce: &[u8]) -> ChaCha20Poly1305 {
+    let nonce = &header_and_nonce[header_and_nonce.len() - NONCE_SIZE..];
+    let key = hkdf(nonce, PAYLOAD_KEY_LABEL, file_key.expose_secret());
+    ChaCha20Poly1305::new(&key.into())
+}
+
+/// Reads an age file's header and payload nonce from `reader`, unwrapping
+/// the file key with `identities`; `age` itself checks the header's MAC.
+///
+/// Returns the file key and the raw bytes of the header and nonce, leaving
+/// `reader` at the start of the payload.
+pub(crate) fn read_header<R: Read>(
+    reader: R,
+    identities: &[&dyn Identity],
+) -> Result<(FileKey, Vec<u8>), RageDecryptError> {
+    let capture = CaptureIdentity {
+        identities,
+        file_key: RefCell::new(None),
+    };
+
+    // `Decryptor::new` reads exactly the header and nonce, without buffering
+    // ahead; its payload reader is unused.
+    let mut reader = Record::new(reader);
+    drop(
+        Decryptor::new(&mut reader)
+            .and_then(|d| d.decrypt(iter::once(&capture as &dyn Identity)))?,
+    );
+
+    let file_key = capture
+        .file_key
+        .into_inner()
+        .expect("an identity unwrapped the file key");
+
+    Ok((file_key, reader.bytes))
+}
+
+// The STREAM nonce: an 11-byte big-endian chunk counter, then a flag byte
+// that's set only on the last chunk.
+fn nonce(counter: u64, last: bool) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce.into()
+}
+
+/// Calls `f` with each item and its index, split across up to `threads`
+/// scoped threads. Returns the first error, if any.
+fn for_each<T: Send>(
+    threads: usize,
+    items: &mut [T],
+    f: impl Fn(usize, &mut T) -> io::Result<()> + Sync,
+) -> io::Result<()> {
+    let per_thread = items.len().div_ceil(threads).max(1);
+
+    thread::scope(|scope| {
+        let handles = items
+            .chunks_mut(per_thread)
+            .enumerate()
+            .map(|(i, part)| {
+                let f = &f;
+                scope.spawn(move || {
+                    part.iter_mut()
+                        .enumerate()
+                        .try_for_each(|(j, item)| f(i * per_thread + j, item))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .try_for_each(|h| h.join().unwrap_or_else(|p| std::panic::resume_unwind(p)))
+    })
+}
+
+/// Reads until `buf` is full or the input ends, returning the bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// Reads the input in batches that fill `buf`, calling `f` with each batch
+/// and whether it's the last one.
+///
+/// A batch is only known to be the last if the input ends before `buf` is
+/// full, so a full batch is followed by a one-byte peek; this guarantees
+/// that a last batch is only ever empty if the whole input is.
+///
+/// As in `copy::copy`, the GIL is released while reading.
+fn batches<R: Read + Send>(
+    py: Python<'_>,
+    reader: &mut R,
+    buf: &mut [u8],
+    mut f: impl FnMut(&mut [u8], bool) -> PyResult<()>,
+    io_error: impl Fn(io::Error) -> PyErr,
+) -> PyResult<()> {
+    let mut carry = None;
+    loop {
+        let start = match carry.take() {
+            Some(byte) => {
+                buf[0] = byte;
+                1
+            }
+            None => 0,
+        };
+        let n = start
+            + py.allow_threads(|| read_full(reader, &mut buf[start..]))
+                .map_err(&io_error)?;
+
+        let last = n < buf.len() || {
+            let mut peek = [0];
+            match py
+                .allow_threads(|| read_full(reader, &mut peek))
+                .map_err(&io_error)?
+            {
+                0 => true,
+                _ => {
+                    carry = Some(peek[0]);
+                    false
+                }
+            }
+        };
+
+        f(&mut buf[..n], last)?;
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+/// Encrypts `reader` to `recipients`, writing a complete age file to
+/// `output`, with the payload's chunks encrypted on up to `threads` threads.
+///
+/// The header is produced by `age` itself, so the result is an ordinary age
+/// file that any implementation can decrypt.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encrypt<R: Read + Send, W: Write + Send>(
+    py: Python<'_>,
+    recipients: &[&dyn Recipient],
+    reader: &mut R,
+    output: &mut W,
+    threads: usize,
+    mut progress: Option<Progress<'_>>,
+    cancel: Option<&CancellationToken>,
+) -> PyResult<()> {
+    let io_error = |e: io::Error| EncryptError::new_err(e.to_string());
+
+    let file_key = RefCell::new(None);
+    let capture = recipients
+        .iter()
+        .map(|&inner| CaptureRecipient {
+            inner,
+            file_key: &file_key,
+        })
+        .collect::<Vec<_>>();
+    let encryptor = Encryptor::with_recipients(capture.iter().map(|r| r as &dyn Recipient))
+        .map_err(encryptor_error)?;
+
+    // This writes the header and nonce; the returned writer is discarded
+    // without writing anything else.
+    let mut output = Record::new(output);
+    drop(encryptor.wrap_output(&mut output).map_err(io_error)?);
+
+    let file_key = file_key
+        .into_inner()
+        .expect("at least one recipient wrapped the file key");
+    let aead = payload_key(&file_key, &output.bytes);
+    let output = output.inner;
+
+    let mut plaintext = vec![0; threads * CHUNKS_PER_THREAD * CHUNK_SIZE];
+    let mut ciphertext = vec![0; threads * CHUNKS_PER_THREAD * ENCRYPTED_CHUNK_SIZE];
+    let mut counter = 0;
+
+    batches(
+        py,
+        reader,
+        &mut plaintext,
+        |plaintext, last| {
+            copy::check(py, cancel)?;
+
+            // An empty input still has one (empty) chunk.
+            let chunks = plaintext.len().div_ceil(CHUNK_SIZE).max(1);
+            let ciphertext = &mut ciphertext[..plaintext.len() + chunks * TAG_SIZE];
+
+            py.allow_threads(|| {
+                let mut items = ciphertext
+                    .chunks_mut(ENCRYPTED_CHUNK_SIZE)
+                    .zip(plaintext.chunks(CHUNK_SIZE).chain(iter::once(&[][..])))
+                    .collect::<Vec<_>>();
+
+                for_each(threads, &mut items, |i, (out, chunk)| {
+                    let (body, tag) = out.split_at_mut(chunk.len());
+                    body.copy_from_slice(chunk);
+                    let nonce = nonce(counter + i as u64, last && i == chunks - 1);
+                    tag.copy_from_slice(
+                        &aead
+                            .encrypt_in_place_detached(&nonce, &[], body)
+                            .expect("chunks are far below ChaCha20's limits"),
+                    );
+                    Ok(())
+                })?;
+                output.write_all(ciphertext)
+            })
+            .map_err(io_error)?;
+            counter += chunks as u64;
+
+            match progress.as_mut() {
+                Some(progress) => progress.report(last),
+                None => Ok(()),
+            }
+        },
+        io_error,
+    )
+}
+
+/// Decrypts the age file in `reader` with `identities`, writing the plaintext
+/// to `writer`, with the payload's chunks authenticated and decrypted on up
+/// to `threads` threads.
+///
+/// As with `age` itself, plaintext is written as it's authenticated, so a
+/// truncated or corrupted input fails only after everything before the
+/// damage has been written.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decrypt<R: Read + Send, W: Write + Send>(
+    py: Python<'_>,
+    tracker: &Tracker<'_>,
+    mut reader: R,
+    writer: &mut W,
+    threads: usize,
+    mut progress: Option<Progress<'_>>,
+    cancel: Option<&CancellationToken>,
+    io_error: impl Fn(io::Error) -> PyErr,
+) -> PyResult<()> {
+    let (file_key, header) = read_header(&mut reader, &[tracker]).map_err(|e| tracker.error(e))?;
+    let aead = payload_key(&file_key, &header);
+
+    let mut buf = vec![0; threads * CHUNKS_PER_THREAD * ENCRYPTED_CHUNK_SIZE];
+    let mut counter = 0;
+
+    batches(
+        py,
+        &mut reader,
+        &mut buf,
+        |ciphertext, last| {
+            copy::check(py, cancel)?;
+
+            if ciphertext.is_empty() {
+                return Err(io_error(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "age file is truncated",
+                )));
+            }
+
+            let chunks = ciphertext.len().div_ceil(ENCRYPTED_CHUNK_SIZE);
+            let mut items = ciphertext
+                .chunks_mut(ENCRYPTED_CHUNK_SIZE)
+                .collect::<Vec<_>>();
+
+            py.allow_threads(|| {
+                for_each(threads, &mut items, |i, chunk| {
+                    let is_last = last && i == chunks - 1;
+                    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "decryption error");
+
+                    if chunk.len() < TAG_SIZE {
+                        return Err(invalid());
+                    }
+                    let full = chunk.len() == ENCRYPTED_CHUNK_SIZE;
+                    let empty = chunk.len() == TAG_SIZE;
+
+                    let (body, tag) = chunk.split_at_mut(chunk.len() - TAG_SIZE);
+                    let tag = Tag::from_slice(tag);
+                    let counter = counter + i as u64;
+                    let open = |body: &mut [u8], last| {
+                        aead.decrypt_in_place_detached(&nonce(counter, last), &[], body, tag)
+                    };
+
+                    match open(body, is_last) {
+                        Ok(()) if is_last && empty && counter > 0 => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "last STREAM chunk is empty",
+                        )),
+                        Ok(()) => Ok(()),
+                        // A full final chunk that isn't marked as the last one
+                        // means that the chunks after it are missing.
+                        Err(_) if is_last && full && open(body, false).is_ok() => Err(
+                            io::Error::new(io::ErrorKind::UnexpectedEof, "age file is truncated"),
+                        ),
+                        Err(_) => Err(invalid()),
+                    }
+                })?;
+
+                items
+                    .iter()
+                    .try_for_each(|chunk| writer.write_all(&chunk[..chunk.len() - TAG_SIZE]))
+            })
+            .map_err(&io_error)?;
+            counter += chunks as u64;
+
+            match progress.as_mut() {
+                Some(progress) => progress.report(last),
+                None => Ok(()),
+            }
+        },
+        &io_error,
+    )
+}
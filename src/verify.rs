@@ -0,0 +1,170 @@
+//! Checking that age files can be decrypted, without keeping the plaintext.
+
+use std::io::{self, BufReader, Read};
+
+use age::{armor::ArmoredReader, DecryptError as RageDecryptError, Identity};
+use pyo3::{prelude::*, types::PyInt, types::PyString};
+
+use crate::{
+    armor::{InputFormat, Peeked},
+    buffer::Buffer,
+    copy::{CancellationToken, CountingReader, Progress, DEFAULT_PROGRESS_INTERVAL},
+    decrypt_stream, decryptor_error,
+    file::FileArg,
+    info::{DecryptInfo, Tracker},
+    io_error,
+    keys::Identities,
+    limits::Limits,
+    stream, DecryptError,
+};
+
+/// A `data_or_path` argument: an age file as bytes-like data, or a path
+/// (`str` or `os.PathLike`) or file descriptor to read it from.
+///
+/// Unlike the `*_file` APIs, `bytes` is always data here, never a path.
+pub(crate) enum Input<'py> {
+    Data(Buffer<'py>),
+    File(FileArg),
+}
+
+impl<'py> FromPyObject<'py> for Input<'py> {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let py = ob.py();
+        if ob.is_instance_of::<PyString>()
+            || ob.is_instance_of::<PyInt>()
+            || ob.hasattr(pyo3::intern!(py, "__fspath__"))?
+        {
+            Ok(Self::File(ob.extract()?))
+        } else {
+            Ok(Self::Data(ob.extract()?))
+        }
+    }
+}
+
+impl Input<'_> {
+    fn size(&self) -> Option<u64> {
+        match self {
+            Self::Data(data) => Some(data.len() as u64),
+            Self::File(file) => file.size(),
+        }
+    }
+
+    fn open(&self, py: Python<'_>) -> PyResult<Box<dyn Read + Send + '_>> {
+        match self {
+            Self::Data(data) => Ok(data.reader()),
+            Self::File(file) => Ok(file.clone().open(py)?),
+        }
+    }
+}
+
+type Reader<'a> = Peeked<BufReader<CountingReader<Box<dyn Read + Send + 'a>>>>;
+
+/// Opens `input` and checks it against `format`, returning a reader and the
+/// input's size, if it's known.
+fn open<'a>(
+    py: Python<'_>,
+    input: &'a Input<'_>,
+    format: InputFormat,
+) -> PyResult<(Reader<'a>, Option<u64>)> {
+    let total = input.size();
+    let reader = format.check_reader(BufReader::new(CountingReader::new(input.open(py)?)))?;
+
+    Ok((reader, total))
+}
+
+/// Whether any of `identities` can decrypt the file, found by reading only
+/// its header: the file key is unwrapped, and the header's MAC checked, but
+/// none of the payload is read.
+///
+/// With `return_index=True`, returns the index of the identity that opened
+/// the file, or `-1` if none did. A malformed or tampered header raises
+/// `DecryptError` either way.
+#[pyfunction]
+#[pyo3(signature = (data_or_path, identities, format=InputFormat::Auto, *, return_index=false, max_header_size=None, max_stanzas=None))]
+pub(crate) fn can_decrypt<'p>(
+    py: Python<'p>,
+    data_or_path: Input<'_>,
+    identities: Identities,
+    format: InputFormat,
+    return_index: bool,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<Bound<'p, PyAny>> {
+    // Only the header is read, so there's no plaintext to limit.
+    let limits = Limits::new(None, max_header_size, max_stanzas);
+    let (reader, _) = open(py, &data_or_path, format)?;
+    let tracker = Tracker::new(&identities);
+
+    let decryptor = age::Decryptor::new_buffered(limits.header(ArmoredReader::new(reader)))
+        .map_err(decryptor_error)?;
+    // This stops after the payload nonce; dropping the returned reader
+    // leaves the payload unread.
+    let index = match decryptor.decrypt(std::iter::once(&tracker as &dyn Identity)) {
+        Ok(_) => Some(tracker.info().identity_index() as i64),
+        Err(RageDecryptError::NoMatchingKeys) => None,
+        Err(e) => return Err(tracker.error(e)),
+    };
+
+    match return_index {
+        true => Ok(index.unwrap_or(-1).into_pyobject(py)?.into_any()),
+        false => Ok(index.is_some().into_pyobject(py)?.to_owned().into_any()),
+    }
+}
+
+// As with `decrypt`, but unlike the file APIs, a payload that fails to
+// authenticate is a `DecryptError` rather than an `OSError`.
+fn payload_error(e: io::Error) -> PyErr {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+            io_error(e, DecryptError::new_err)
+        }
+        _ => e.into(),
+    }
+}
+
+/// Decrypts and authenticates the whole file, discarding the plaintext.
+///
+/// Raises `DecryptError` if no identity can open the file, or if any STREAM
+/// chunk fails to authenticate or is missing.
+#[pyfunction]
+#[pyo3(signature = (data_or_path, identities, format=InputFormat::Auto, *, progress=None, progress_interval=DEFAULT_PROGRESS_INTERVAL, cancel=None, threads=1, return_info=false, max_plaintext_size=None, max_header_size=None, max_stanzas=None))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn verify(
+    py: Python<'_>,
+    data_or_path: Input<'_>,
+    identities: Identities,
+    format: InputFormat,
+    progress: Option<Bound<'_, PyAny>>,
+    progress_interval: u64,
+    cancel: Option<PyRef<'_, CancellationToken>>,
+    threads: usize,
+    return_info: bool,
+    max_plaintext_size: Option<u64>,
+    max_header_size: Option<usize>,
+    max_stanzas: Option<usize>,
+) -> PyResult<Option<DecryptInfo>> {
+    let threads = stream::threads(threads)?;
+    let limits = Limits::new(max_plaintext_size, max_header_size, max_stanzas);
+
+    let (reader, total) = open(py, &data_or_path, format)?;
+    let progress = Progress::new(
+        progress,
+        progress_interval,
+        total,
+        reader.get_ref().1.get_ref().count(),
+    );
+
+    let info = decrypt_stream(
+        py,
+        &identities,
+        reader,
+        &mut io::sink(),
+        limits,
+        threads,
+        progress,
+        cancel.as_deref(),
+        payload_error,
+    )?;
+
+    Ok(return_info.then_some(info))
+}
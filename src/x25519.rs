@@ -5,7 +5,7 @@ use pyo3::{prelude::*, types::PyType};
 
 use crate::{IdentityError, RecipientError};
 
-#[pyclass(module = "pyrage.x25519")]
+#[pyclass(module = "pyrage.x25519", frozen)]
 #[derive(Clone)]
 pub(crate) struct Recipient(pub(crate) age::x25519::Recipient);
 
@@ -23,7 +23,7 @@ impl Recipient {
     }
 }
 
-#[pyclass(module = "pyrage.x25519")]
+#[pyclass(module = "pyrage.x25519", frozen)]
 #[derive(Clone)]
 pub(crate) struct Identity(pub(crate) age::x25519::Identity);
 
@@ -46,13 +46,19 @@ impl Identity {
         Recipient(self.0.to_public())
     }
 
-    fn __str__(&self) -> String {
+    /// Returns the secret key, which `str()` deliberately doesn't.
+    fn reveal_secret(&self) -> String {
         self.0.to_string().expose_secret().into()
     }
+
+    fn __repr__(&self) -> String {
+        format!("Identity(public={:?})", self.0.to_public().to_string())
+    }
 }
 
 pub(crate) fn module(py: Python<'_>) -> PyResult<Bound<'_, PyModule>> {
     let module = PyModule::new(py, "x25519")?;
+    module.gil_used(false)?;
 
     module.add_class::<Recipient>()?;
     module.add_class::<Identity>()?;
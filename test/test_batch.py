@@ -0,0 +1,177 @@
+import os
+import sys
+import tempfile
+import unittest
+from pathlib import Path
+
+import pyrage
+
+
+class TestBatch(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.TemporaryDirectory()
+        self.root = Path(self.tempdir.name)
+        self.identity = pyrage.x25519.Identity.generate()
+        self.recipient = self.identity.to_public()
+
+    def tearDown(self):
+        self.tempdir.cleanup()
+
+    def test_roundtrip_files(self):
+        files = []
+        for i in range(20):
+            source = self.root / f"file{i}"
+            source.write_bytes(b"test%d" % i)
+            files.append((source, self.root / f"file{i}.age"))
+
+        results = pyrage.encrypt_files(files, [self.recipient], workers=4)
+        self.assertEqual(len(results), 20)
+        self.assertTrue(all(result.ok for result in results))
+        self.assertEqual([result.source for result in results], [s for s, _ in files])
+
+        decrypted = [(d, self.root / f"{s.name}.out") for s, d in files]
+        results = pyrage.decrypt_files(decrypted, [self.identity], workers=4)
+        self.assertTrue(all(result.ok for result in results))
+
+        for i, (_, out) in enumerate(decrypted):
+            self.assertEqual(out.read_bytes(), b"test%d" % i)
+
+    def test_per_file_errors(self):
+        good = self.root / "good"
+        good.write_bytes(b"test")
+
+        results = pyrage.encrypt_files(
+            [(good, self.root / "good.age"), (self.root / "missing", self.root / "missing.age")],
+            [self.recipient],
+        )
+        self.assertTrue(results[0].ok)
+        self.assertIsNone(results[0].error)
+        self.assertFalse(results[1].ok)
+        self.assertIsInstance(results[1].error, OSError)
+        self.assertFalse((self.root / "missing.age").exists())
+
+        other = pyrage.x25519.Identity.generate()
+        results = pyrage.decrypt_files([(self.root / "good.age", self.root / "out")], [other])
+        self.assertIsInstance(results[0].error, pyrage.DecryptError)
+        self.assertFalse((self.root / "out").exists())
+
+    def test_invalid_workers(self):
+        with self.assertRaisesRegex(ValueError, "workers must be at least 1"):
+            pyrage.encrypt_files([], [self.recipient], workers=0)
+
+    def test_cancelled(self):
+        source = self.root / "file"
+        source.write_bytes(b"test")
+
+        token = pyrage.CancellationToken()
+        token.cancel()
+        with self.assertRaises(pyrage.CancelledError):
+            pyrage.encrypt_files([(source, self.root / "file.age")], [self.recipient], cancel=token)
+        self.assertFalse((self.root / "file.age").exists())
+
+    def test_roundtrip_tree(self):
+        source = self.root / "source"
+        (source / "a" / "b").mkdir(parents=True)
+        (source / "skipped").mkdir()
+        (source / "top.txt").write_bytes(b"top")
+        (source / "a" / "b" / "nested.txt").write_bytes(b"nested")
+        (source / "a" / "debug.log").write_bytes(b"log")
+        (source / "skipped" / "file.txt").write_bytes(b"skipped")
+
+        encrypted = self.root / "encrypted"
+        results = pyrage.encrypt_tree(
+            source,
+            encrypted,
+            [self.recipient],
+            exclude=["skipped", "**/*.log"],
+        )
+        self.assertTrue(all(result.ok for result in results))
+        self.assertEqual(
+            sorted(str(p.relative_to(encrypted)) for p in encrypted.rglob("*") if p.is_file()),
+            [os.path.join("a", "b", "nested.txt.age"), "top.txt.age"],
+        )
+
+        decrypted = self.root / "decrypted"
+        results = pyrage.decrypt_tree(
+            encrypted, decrypted, [self.identity], include=["a/**"]
+        )
+        self.assertEqual(len(results), 1)
+        self.assertEqual((decrypted / "a" / "b" / "nested.txt").read_bytes(), b"nested")
+        self.assertFalse((decrypted / "top.txt").exists())
+
+    @unittest.skipIf(sys.platform == "win32", "requires symlinks and FIFOs")
+    def test_tree_skipped(self):
+        source = self.root / "source"
+        source.mkdir()
+        (source / "file.txt").write_bytes(b"file")
+        (source / "link.txt").symlink_to(source / "file.txt")
+        (source / "linked-dir").symlink_to(self.root, target_is_directory=True)
+        os.mkfifo(source / "fifo")
+
+        encrypted = self.root / "encrypted"
+        results = pyrage.encrypt_tree(source, encrypted, [self.recipient])
+
+        self.assertEqual(
+            [(r.source.name, r.ok, r.skipped) for r in results],
+            [
+                ("fifo", False, "not a regular file"),
+                ("file.txt", True, None),
+                ("link.txt", False, "symlinks aren't followed"),
+                ("linked-dir", False, "symlinks aren't followed"),
+            ],
+        )
+        self.assertIsNone(results[0].error)
+        self.assertIn('skipped="not a regular file"', repr(results[0]))
+        self.assertEqual(
+            sorted(p.name for p in encrypted.iterdir()), ["file.txt.age"]
+        )
+
+    def test_decrypt_tree_skipped(self):
+        source = self.root / "source"
+        source.mkdir()
+        (source / "file.txt.age").write_bytes(pyrage.encrypt(b"file", [self.recipient]))
+        (source / "notes.txt").write_bytes(b"notes")
+
+        decrypted = self.root / "decrypted"
+        results = pyrage.decrypt_tree(source, decrypted, [self.identity])
+
+        self.assertEqual(
+            [(r.source.name, r.destination.name, r.ok, r.skipped) for r in results],
+            [
+                ("file.txt.age", "file.txt", True, None),
+                ("notes.txt", "notes.txt", False, "no .age extension"),
+            ],
+        )
+        self.assertEqual(sorted(p.name for p in decrypted.iterdir()), ["file.txt"])
+
+    def test_tree_destination_inside_source(self):
+        source = self.root / "source"
+        source.mkdir()
+        (source / "file.txt").write_bytes(b"file")
+
+        for destination in [source, source / "encrypted", source / "a" / ".." / "b"]:
+            with self.assertRaisesRegex(ValueError, "inside the source directory"):
+                pyrage.encrypt_tree(source, destination, [self.recipient])
+        self.assertEqual([p.name for p in source.iterdir()], ["file.txt"])
+
+        # a sibling that merely shares a prefix is fine
+        results = pyrage.encrypt_tree(source, self.root / "source-encrypted", [self.recipient])
+        self.assertTrue(all(r.ok for r in results))
+
+    def test_invalid_glob(self):
+        with self.assertRaises(ValueError):
+            pyrage.encrypt_tree(self.root, self.root / "out", [self.recipient], include=["a/**["])
+
+    def test_decrypt_files_limits(self):
+        source = self.root / "big.age"
+        source.write_bytes(pyrage.encrypt(b"x" * 1000, [self.recipient]))
+
+        (result,) = pyrage.decrypt_files(
+            [(source, self.root / "big")], [self.identity], max_plaintext_size=10
+        )
+        self.assertIsInstance(result.error, pyrage.LimitExceededError)
+        self.assertFalse((self.root / "big").exists())
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,160 @@
+import subprocess
+import sys
+import tempfile
+import unittest
+from pathlib import Path
+
+import pyrage
+
+from .utils import _ASSETS, ssh_keypair
+
+
+def run(*args, input=b""):
+    return subprocess.run(
+        [sys.executable, "-c", "import sys, pyrage; sys.exit(pyrage.main())", *args],
+        input=input,
+        capture_output=True,
+        # no controlling terminal, so passphrase prompts read from stdin
+        start_new_session=True,
+    )
+
+
+class TestCli(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.TemporaryDirectory()
+        self.root = Path(self.tempdir.name)
+        self.identity = pyrage.x25519.Identity.generate()
+        self.recipient = str(self.identity.to_public())
+        self.identity_file = self.root / "key.txt"
+        self.identity_file.write_text(f"# created: today\n\n{self.identity.reveal_secret()}\n")
+
+    def tearDown(self):
+        self.tempdir.cleanup()
+
+    def test_help_and_version(self):
+        result = run("--help")
+        self.assertEqual(result.returncode, 0)
+        self.assertIn(b"Usage: pyrage", result.stdout)
+
+        result = run("-V")
+        self.assertEqual(result.returncode, 0)
+        self.assertTrue(result.stdout.startswith(b"pyrage "))
+
+    def test_roundtrip_files(self):
+        plaintext = self.root / "plaintext"
+        plaintext.write_bytes(b"test")
+
+        result = run("-r", self.recipient, "-o", str(self.root / "encrypted"), str(plaintext))
+        self.assertEqual(result.returncode, 0, result.stderr)
+        encrypted = (self.root / "encrypted").read_bytes()
+        self.assertEqual(pyrage.decrypt(encrypted, [self.identity]), b"test")
+
+        result = run(
+            "-d", "-i", str(self.identity_file), "-o", str(self.root / "decrypted"),
+            str(self.root / "encrypted"),
+        )
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertEqual((self.root / "decrypted").read_bytes(), b"test")
+
+    def test_roundtrip_stdio(self):
+        result = run("-a", f"--recipient={self.recipient}", input=b"test")
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertTrue(pyrage.is_armored(result.stdout))
+
+        result = run("-d", f"-i{self.identity_file}", "-", input=result.stdout)
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertEqual(result.stdout, b"test")
+
+    def test_recipients_file_and_identity(self):
+        other = pyrage.x25519.Identity.generate()
+        pubkey, _ = ssh_keypair("ed25519")
+        recipients_file = self.root / "recipients.txt"
+        recipients_file.write_text(f"# team\n{other.to_public()}\n\n{pubkey}\n")
+
+        result = run("-R", str(recipients_file), "-i", str(self.identity_file), input=b"test")
+        self.assertEqual(result.returncode, 0, result.stderr)
+
+        for identity in (other, self.identity):
+            self.assertEqual(pyrage.decrypt(result.stdout, [identity]), b"test")
+
+        # short flags can be bundled
+        result = run("-di", str(self.identity_file), input=result.stdout)
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertEqual(result.stdout, b"test")
+
+        result = run("-da", "-i", str(self.identity_file), input=result.stdout)
+        self.assertEqual(result.returncode, 1)
+        self.assertIn(b"can't be used with -d", result.stderr)
+
+    def test_ssh_identity_file(self):
+        pubkey, _ = ssh_keypair("ed25519")
+        encrypted = pyrage.encrypt(b"test", [pyrage.ssh.Recipient.from_str(pubkey)])
+
+        result = run("-d", "-i", str(_ASSETS / "ed25519"), input=encrypted)
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertEqual(result.stdout, b"test")
+
+    def test_passphrase(self):
+        plaintext = self.root / "plaintext"
+        plaintext.write_bytes(b"test")
+        encrypted = self.root / "encrypted"
+
+        result = run("-p", "-o", str(encrypted), str(plaintext), input=b"secret\nsecret\n")
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertEqual(pyrage.passphrase.decrypt(encrypted.read_bytes(), "secret"), b"test")
+
+        result = run("-d", str(encrypted), input=b"secret\n")
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertEqual(result.stdout, b"test")
+
+        result = run("-p", str(plaintext), input=b"secret\nother\n")
+        self.assertEqual(result.returncode, 1)
+        self.assertIn(b"passphrases didn't match", result.stderr)
+
+    def test_encrypted_identity_file(self):
+        protected = self.root / "key.age"
+        protected.write_bytes(
+            pyrage.passphrase.encrypt(self.identity_file.read_bytes(), "secret", armored=True)
+        )
+        encrypted = pyrage.encrypt(b"test", [self.identity.to_public()])
+        encrypted_file = self.root / "encrypted"
+        encrypted_file.write_bytes(encrypted)
+
+        result = run("-d", "-i", str(protected), str(encrypted_file), input=b"secret\n")
+        self.assertEqual(result.returncode, 0, result.stderr)
+        self.assertEqual(result.stdout, b"test")
+
+    def test_errors(self):
+        cases = [
+            (["-e", "-d"], b"can't be used with -d/--decrypt"),
+            ([], b"missing recipients"),
+            (["-r", "age1invalid"], b"invalid recipient: age1invalid"),
+            (["-p", "-r", self.recipient], b"can't be used with -r"),
+            (["-d"], b"missing identities"),
+            (["--frobnicate"], b"unknown option: --frobnicate"),
+            (["-o"], b"-o requires a value"),
+            (["-j", "../x"], b"invalid plugin name"),
+            (["-r", self.recipient, "a", "b"], b"only one input file"),
+        ]
+        encrypted = pyrage.encrypt(b"test", [self.identity.to_public()])
+        for args, message in cases:
+            result = run(*args, input=encrypted)
+            self.assertEqual(result.returncode, 1, args)
+            self.assertIn(b"error: ", result.stderr)
+            self.assertIn(message, result.stderr)
+
+        other = self.root / "other.txt"
+        other.write_text(f"{pyrage.x25519.Identity.generate().reveal_secret()}\n")
+        result = run("-d", "-i", str(other), "-o", str(self.root / "out"), input=encrypted)
+        self.assertEqual(result.returncode, 1)
+        self.assertIn(b"No matching keys found", result.stderr)
+        self.assertFalse((self.root / "out").exists())
+
+        bad = self.root / "bad.txt"
+        bad.write_text("# comment\nnot a key\n")
+        result = run("-d", "-i", str(bad), input=encrypted)
+        self.assertIn(b"bad.txt:2: invalid identity", result.stderr)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -1,8 +1,37 @@
+import os
+import stat
+import sys
+import tempfile
 import unittest
 
 from parameterized import parameterized
 
-from pyrage import passphrase
+import pyrage
+from pyrage import CancelledError, DecryptError, LimitExceededError, passphrase, x25519
+
+# A stand-in for a pinentry program, which answers `GETPIN` with
+# `$FAKE_PINENTRY_PIN`, or cancels if it's unset.
+_PINENTRY_SOURCE = """
+import os, sys
+
+def send(line):
+    sys.stdout.write(line + "\\n")
+    sys.stdout.flush()
+
+send("OK Pleased to meet you")
+for line in sys.stdin:
+    command = line.split(" ", 1)[0].strip()
+    if command == "BYE":
+        send("OK closing connection")
+        break
+    elif command == "GETPIN":
+        pin = os.environ.get("FAKE_PINENTRY_PIN")
+        if pin is None:
+            send("ERR 83886179 Operation cancelled <Pinentry>")
+            continue
+        send("D " + pin.replace("%", "%25"))
+    send("OK")
+"""
 
 
 class TestPassphrase(unittest.TestCase):
@@ -13,3 +42,148 @@ class TestPassphrase(unittest.TestCase):
         decrypted = passphrase.decrypt(encrypted, "some password")
 
         self.assertEqual(plaintext, decrypted)
+
+    def test_buffer_inputs(self):
+        encrypted = passphrase.encrypt(memoryview(b"junk"), "some password")
+        decrypted = passphrase.decrypt(bytearray(encrypted), "some password")
+        self.assertEqual(decrypted, b"junk")
+
+    def test_decrypt_str(self):
+        encrypted = passphrase.encrypt(b"junk", "some password", armored=True)
+        decrypted = passphrase.decrypt(encrypted.decode(), "some password")
+        self.assertEqual(decrypted, b"junk")
+
+    def test_bytearray_passphrase_is_wiped(self):
+        password = bytearray(b"some password")
+        encrypted = passphrase.encrypt(b"junk", password)
+        self.assertEqual(password, bytearray(len(password)))
+
+        password = bytearray(b"some password")
+        decrypted = passphrase.decrypt(encrypted, password, output_type="bytearray")
+        self.assertEqual(decrypted, bytearray(b"junk"))
+        self.assertEqual(password, bytearray(len(password)))
+
+    def test_bytearray_passphrase_invalid_utf8(self):
+        with self.assertRaisesRegex(ValueError, "passphrase must be valid UTF-8"):
+            passphrase.encrypt(b"junk", bytearray(b"\xff"))
+
+    def test_decrypt_format_mismatch(self):
+        encrypted = passphrase.encrypt(b"junk", "some password", armored=True)
+        with self.assertRaisesRegex(DecryptError, "expected a binary age file"):
+            passphrase.decrypt(encrypted, "some password", format="binary")
+
+    def test_decrypt_prompt(self):
+        encrypted = passphrase.encrypt(b"junk", "some password")
+        descriptions = []
+
+        def prompt(description):
+            descriptions.append(description)
+            return "some password"
+
+        self.assertEqual(passphrase.decrypt(encrypted, prompt), b"junk")
+        self.assertEqual(descriptions, ["Enter passphrase:"])
+
+    def test_decrypt_prompt_callbacks(self):
+        class Callbacks:
+            def request_passphrase(self, description):
+                return bytearray(b"some password")
+
+        encrypted = passphrase.encrypt(b"junk", "some password")
+        self.assertEqual(passphrase.decrypt(encrypted, Callbacks()), b"junk")
+
+    def test_decrypt_prompt_retries(self):
+        encrypted = passphrase.encrypt(b"junk", "some password")
+        answers = iter(["wrong", "also wrong", "some password"])
+        descriptions = []
+
+        def prompt(description):
+            descriptions.append(description)
+            return next(answers)
+
+        self.assertEqual(passphrase.decrypt(encrypted, prompt), b"junk")
+        self.assertEqual(len(descriptions), 3)
+        self.assertIn("Incorrect passphrase", descriptions[1])
+
+    def test_decrypt_prompt_max_attempts(self):
+        encrypted = passphrase.encrypt(b"junk", "some password")
+        calls = []
+
+        def prompt(description):
+            calls.append(description)
+            return "wrong"
+
+        with self.assertRaises(DecryptError):
+            passphrase.decrypt(encrypted, prompt, max_attempts=2)
+        self.assertEqual(len(calls), 2)
+
+        with self.assertRaisesRegex(ValueError, "max_attempts must be at least 1"):
+            passphrase.decrypt(encrypted, prompt, max_attempts=0)
+
+    def test_decrypt_prompt_cancelled(self):
+        encrypted = passphrase.encrypt(b"junk", "some password")
+        with self.assertRaisesRegex(CancelledError, "passphrase entry cancelled"):
+            passphrase.decrypt(encrypted, lambda description: None)
+
+    def test_decrypt_prompt_not_scrypt(self):
+        identity = x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"junk", [identity.to_public()])
+
+        def prompt(description):
+            self.fail("prompted for a key-encrypted file")
+
+        with self.assertRaisesRegex(DecryptError, "isn't passphrase-encrypted"):
+            passphrase.decrypt(encrypted, prompt)
+
+    def test_decrypt_prompt_invalid_type(self):
+        encrypted = passphrase.encrypt(b"junk", "some password")
+        with self.assertRaisesRegex(TypeError, "expected a str or bytearray"):
+            passphrase.decrypt(encrypted, 1234)
+
+    def test_decrypt_limits(self):
+        encrypted = passphrase.encrypt(b"junk", "some password")
+
+        with self.assertRaisesRegex(LimitExceededError, "max_work_factor"):
+            passphrase.decrypt(encrypted, "some password", max_work_factor=1)
+        with self.assertRaisesRegex(LimitExceededError, "max_plaintext_size"):
+            passphrase.decrypt(encrypted, "some password", max_plaintext_size=2)
+        with self.assertRaisesRegex(LimitExceededError, "max_header_size"):
+            passphrase.decrypt(encrypted, "some password", max_header_size=50)
+
+        decrypted = passphrase.decrypt(
+            encrypted, "some password", max_work_factor=22, max_stanzas=1
+        )
+        self.assertEqual(decrypted, b"junk")
+
+
+@unittest.skipIf(sys.platform == "win32", "pinentry script requires a shebang")
+class TestPinentry(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.TemporaryDirectory()
+        self.path = os.path.join(self.tempdir.name, "pinentry")
+        with open(self.path, "w") as file:
+            file.write(f"#!{sys.executable}\n{_PINENTRY_SOURCE}")
+        os.chmod(self.path, os.stat(self.path).st_mode | stat.S_IEXEC)
+
+    def tearDown(self):
+        os.environ.pop("FAKE_PINENTRY_PIN", None)
+        self.tempdir.cleanup()
+
+    def test_decrypt(self):
+        os.environ["FAKE_PINENTRY_PIN"] = "some 100% password"
+        encrypted = passphrase.encrypt(b"junk", "some 100% password")
+
+        pinentry = passphrase.Pinentry(self.path, title="pyrage")
+        self.assertEqual(passphrase.decrypt(encrypted, pinentry), b"junk")
+
+    def test_cancelled(self):
+        encrypted = passphrase.encrypt(b"junk", "some password")
+
+        pinentry = passphrase.Pinentry(self.path)
+        self.assertIsNone(pinentry.request_passphrase("Enter passphrase:"))
+        with self.assertRaises(CancelledError):
+            passphrase.decrypt(encrypted, pinentry)
+
+    def test_missing_program(self):
+        pinentry = passphrase.Pinentry(os.path.join(self.tempdir.name, "nonexistent"))
+        with self.assertRaises(OSError):
+            pinentry.request_passphrase("Enter passphrase:")
@@ -0,0 +1,322 @@
+import os
+import stat
+import subprocess
+import sys
+import tempfile
+import textwrap
+import time
+import unittest
+from pathlib import Path
+
+import pyrage
+from pyrage import plugin
+
+_PLUGIN_NAME = "pyragetest"
+_PLUGIN_RECIPIENT = "age1pyragetest1qqqqqqqqs799lc"
+
+# A toy plugin: "wraps" the file key by reversing it. Its stanzas are tagged
+# with $PYRAGE_TEST_TAG, if set.
+_PLUGIN_SOURCE = textwrap.dedent(
+    """
+    import os
+    import time
+
+    from pyrage import plugin
+
+
+    TAG = os.environ.get("PYRAGE_TEST_TAG", "pyragetest")
+
+
+    class RecipientImpl:
+        def wrap_file_key(self, file_key, recipients, identities, callbacks):
+            callbacks.display_message("wrapping to " + str(recipients[0]))
+            if "PYRAGE_TEST_INHERITED" in os.environ:
+                callbacks.display_message("inherited environment")
+            if "PYRAGE_TEST_CONFIRM" in os.environ:
+                callbacks.confirm("continue?", "yes", None)
+            return [plugin.Stanza(TAG, ["arg"], file_key[::-1])]
+
+
+    class IdentityImpl:
+        def unwrap_file_key(self, stanzas, identities, callbacks):
+            for stanza in stanzas:
+                if stanza.tag == TAG and stanza.args == ["arg"]:
+                    return stanza.body[::-1]
+            return None
+
+
+    if "PYRAGE_TEST_HANG" in os.environ:
+        time.sleep(60)
+    plugin.serve(recipient_impl=RecipientImpl(), identity_impl=IdentityImpl())
+    """
+)
+
+
+class Callbacks:
+    def __init__(self):
+        self.messages = []
+
+    def display_message(self, message):
+        self.messages.append(message)
+
+    def confirm(self, message, yes_string, no_string):
+        return None
+
+    def request_public_string(self, description):
+        return None
+
+    def request_passphrase(self, description):
+        return None
+
+
+@unittest.skipIf(sys.platform == "win32", "plugin script requires a shebang")
+class TestServe(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.TemporaryDirectory()
+        path = os.path.join(self.tempdir.name, f"age-plugin-{_PLUGIN_NAME}")
+        with open(path, "w") as file:
+            file.write(f"#!{sys.executable}\n{_PLUGIN_SOURCE}")
+        os.chmod(path, os.stat(path).st_mode | stat.S_IEXEC)
+
+        self.old_path = os.environ["PATH"]
+        os.environ["PATH"] = os.pathsep.join([self.tempdir.name, self.old_path])
+
+    def tearDown(self):
+        os.environ["PATH"] = self.old_path
+        self.tempdir.cleanup()
+
+    def test_roundtrip(self):
+        callbacks = Callbacks()
+        recipient = plugin.RecipientPluginV1(
+            _PLUGIN_NAME, [plugin.Recipient.from_str(_PLUGIN_RECIPIENT)], [], callbacks
+        )
+        identity = plugin.IdentityPluginV1(
+            _PLUGIN_NAME,
+            [plugin.Identity.default_for_plugin(_PLUGIN_NAME)],
+            callbacks,
+        )
+
+        encrypted = pyrage.encrypt(b"test", [recipient])
+        self.assertEqual(callbacks.messages, [f"wrapping to {_PLUGIN_RECIPIENT}"])
+        self.assertEqual(pyrage.decrypt(encrypted, [identity]), b"test")
+
+    def test_strings(self):
+        identity = str(plugin.Identity.default_for_plugin(_PLUGIN_NAME))
+
+        recipient = pyrage.parse_recipient(_PLUGIN_RECIPIENT)
+        self.assertIsInstance(recipient, plugin.RecipientPluginV1)
+        self.assertIsInstance(pyrage.parse_identity(identity), plugin.IdentityPluginV1)
+
+        # both strings go to a single run of the plugin, which wraps once
+        encrypted = pyrage.encrypt(b"test", [_PLUGIN_RECIPIENT, _PLUGIN_RECIPIENT])
+        decrypted, info = pyrage.decrypt(encrypted, [identity], return_info=True)
+        self.assertEqual(decrypted, b"test")
+        self.assertEqual(info.stanza_count, 2)
+
+    def test_strings_keep_indexes(self):
+        identity = str(plugin.Identity.default_for_plugin(_PLUGIN_NAME))
+        alice = pyrage.x25519.Identity.generate()
+        bob = pyrage.x25519.Identity.generate()
+        identities = [identity, alice, identity, bob]
+
+        # the plugin strings run as one plugin, but the indexes are the caller's
+        encrypted = pyrage.encrypt(b"test", [bob.to_public()])
+        _, info = pyrage.decrypt(encrypted, identities, return_info=True)
+        self.assertEqual(info.identity_index, 3)
+        self.assertEqual(pyrage.can_decrypt(encrypted, identities, return_index=True), 3)
+
+        encrypted = pyrage.encrypt(b"test", [_PLUGIN_RECIPIENT])
+        _, info = pyrage.decrypt(encrypted, [alice, identity, bob], return_info=True)
+        self.assertEqual(info.identity_index, 1)
+
+    def test_unwrap_no_match(self):
+        callbacks = Callbacks()
+        identity = plugin.IdentityPluginV1(
+            _PLUGIN_NAME,
+            [plugin.Identity.default_for_plugin(_PLUGIN_NAME)],
+            callbacks,
+        )
+
+        encrypted = pyrage.encrypt(
+            b"test", [pyrage.x25519.Identity.generate().to_public()]
+        )
+        with self.assertRaisesRegex(pyrage.DecryptError, "No matching keys found"):
+            pyrage.decrypt(encrypted, [identity])
+
+    def test_serve_unsupported_state_machine(self):
+        with self.assertRaisesRegex(ValueError, "unsupported state machine"):
+            plugin.serve(state_machine="identity-v1")
+
+
+@unittest.skipIf(sys.platform == "win32", "plugin script requires a shebang")
+class TestPluginOptions(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.TemporaryDirectory()
+        self.path = os.path.join(self.tempdir.name, f"age-plugin-{_PLUGIN_NAME}")
+        with open(self.path, "w") as file:
+            file.write(f"#!{sys.executable}\n{_PLUGIN_SOURCE}")
+        os.chmod(self.path, os.stat(self.path).st_mode | stat.S_IEXEC)
+
+        os.environ["PYRAGE_TEST_INHERITED"] = "1"
+
+    def tearDown(self):
+        del os.environ["PYRAGE_TEST_INHERITED"]
+        self.tempdir.cleanup()
+
+    def recipient(self, callbacks, **kwargs):
+        return plugin.RecipientPluginV1(
+            _PLUGIN_NAME,
+            [plugin.Recipient.from_str(_PLUGIN_RECIPIENT)],
+            [],
+            callbacks,
+            **kwargs,
+        )
+
+    def test_missing_from_path(self):
+        with self.assertRaisesRegex(pyrage.EncryptError, f"age-plugin-{_PLUGIN_NAME}"):
+            self.recipient(Callbacks())
+
+    def test_path(self):
+        callbacks = Callbacks()
+        recipient = self.recipient(callbacks, path=Path(self.path))
+        identity = plugin.IdentityPluginV1(
+            _PLUGIN_NAME,
+            [plugin.Identity.default_for_plugin(_PLUGIN_NAME)],
+            callbacks,
+            path=self.path,
+        )
+
+        encrypted = pyrage.encrypt(b"test", [recipient])
+        self.assertIn("inherited environment", callbacks.messages)
+        self.assertEqual(pyrage.decrypt(encrypted, [identity]), b"test")
+
+    def test_bytes_path(self):
+        callbacks = Callbacks()
+        recipient = self.recipient(callbacks, path=os.fsencode(self.path))
+        identity = plugin.IdentityPluginV1(
+            _PLUGIN_NAME,
+            [plugin.Identity.default_for_plugin(_PLUGIN_NAME)],
+            callbacks,
+            path=os.fsencode(self.path),
+        )
+
+        encrypted = pyrage.encrypt(b"test", [recipient])
+        self.assertEqual(pyrage.decrypt(encrypted, [identity]), b"test")
+
+    def test_env(self):
+        callbacks = Callbacks()
+        env = {"PATH": self.tempdir.name, "PYTHONPATH": os.environ.get("PYTHONPATH", "")}
+        recipient = self.recipient(callbacks, env=env)
+
+        pyrage.encrypt(b"test", [recipient])
+        self.assertEqual(callbacks.messages, [f"wrapping to {_PLUGIN_RECIPIENT}"])
+
+    def test_timeout(self):
+        env = {"PYRAGE_TEST_HANG": "1", "PYTHONPATH": os.environ.get("PYTHONPATH", "")}
+        recipient = self.recipient(Callbacks(), path=self.path, env=env, timeout=0.5)
+
+        with self.assertRaisesRegex(pyrage.EncryptError, "did not finish within"):
+            pyrage.encrypt(b"test", [recipient])
+
+    def test_timeout_excludes_callbacks(self):
+        class SlowCallbacks(Callbacks):
+            def confirm(self, message, yes_string, no_string):
+                time.sleep(1)
+                return True
+
+        env = {"PYRAGE_TEST_CONFIRM": "1", "PYTHONPATH": os.environ.get("PYTHONPATH", "")}
+        recipient = self.recipient(SlowCallbacks(), path=self.path, env=env, timeout=0.5)
+
+        pyrage.encrypt(b"test", [recipient])
+
+    def test_invalid_timeout(self):
+        with self.assertRaisesRegex(ValueError, "invalid timeout"):
+            self.recipient(Callbacks(), path=self.path, timeout=-1)
+
+    def test_plugin_processes_reaped(self):
+        def zombies():
+            reaped = []
+            while True:
+                try:
+                    pid, _ = os.waitpid(-1, os.WNOHANG)
+                except ChildProcessError:
+                    return reaped
+                if pid == 0:
+                    return reaped
+                reaped.append(pid)
+
+        zombies()
+        pyrage.encrypt(b"test", [self.recipient(Callbacks(), path=self.path)])
+        self.assertEqual(zombies(), [])
+
+        env = {"PYRAGE_TEST_HANG": "1", "PYTHONPATH": os.environ.get("PYTHONPATH", "")}
+        recipient = self.recipient(Callbacks(), path=self.path, env=env, timeout=0.5)
+        with self.assertRaises(pyrage.EncryptError):
+            pyrage.encrypt(b"test", [recipient])
+        self.assertEqual(zombies(), [])
+
+    def test_rewrap_keeps_postquantum_label(self):
+        identity = plugin.IdentityPluginV1(
+            _PLUGIN_NAME,
+            [plugin.Identity.default_for_plugin(_PLUGIN_NAME)],
+            Callbacks(),
+            path=self.path,
+        )
+        os.environ["PYRAGE_TEST_TAG"] = "mlkem768x25519"
+        try:
+            recipient = self.recipient(Callbacks(), path=self.path)
+            encrypted = pyrage.encrypt(b"test", [recipient])
+            with tempfile.TemporaryDirectory() as tempdir:
+                infile = Path(tempdir) / "in.age"
+                outfile = Path(tempdir) / "out.age"
+                infile.write_bytes(encrypted)
+
+                # a classical recipient would be the weak link
+                classical = pyrage.x25519.Identity.generate().to_public()
+                with self.assertRaisesRegex(pyrage.EncryptError, "postquantum"):
+                    pyrage.rewrap(infile, outfile, [identity], [classical])
+                self.assertFalse(outfile.exists())
+        finally:
+            del os.environ["PYRAGE_TEST_TAG"]
+
+    def test_agedebug(self):
+        script = textwrap.dedent(
+            f"""
+            import pyrage
+            from pyrage import plugin
+
+            class Callbacks:
+                def display_message(self, message):
+                    pass
+
+            recipient = plugin.RecipientPluginV1(
+                {_PLUGIN_NAME!r},
+                [plugin.Recipient.from_str({_PLUGIN_RECIPIENT!r})],
+                [],
+                Callbacks(),
+                path={self.path!r},
+            )
+            pyrage.encrypt(b"test", [recipient])
+            """
+        )
+        result = subprocess.run(
+            [sys.executable, "-c", script],
+            env={**os.environ, "AGEDEBUG": "plugin"},
+            capture_output=True,
+            check=True,
+        )
+
+        self.assertIn(b"-> add-recipient " + _PLUGIN_RECIPIENT.encode(), result.stderr)
+        self.assertIn(b"-> recipient-stanza 0", result.stderr)
+
+
+class TestStanza(unittest.TestCase):
+    def test_fields(self):
+        stanza = plugin.Stanza("tag", ["a", "b"], b"body")
+        self.assertEqual(stanza.tag, "tag")
+        self.assertEqual(stanza.args, ["a", "b"])
+        self.assertEqual(stanza.body, b"body")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -1,7 +1,16 @@
+import array
+import mmap
 import os
+import signal
+import stat
+import sys
+import sysconfig
 import tempfile
+import threading
 import unittest
-from io import BytesIO
+from concurrent.futures import ThreadPoolExecutor
+from io import BytesIO, StringIO
+from pathlib import Path
 
 from parameterized import parameterized
 
@@ -10,6 +19,16 @@ import pyrage
 from .utils import ssh_keypair
 
 
+class ShortReads(BytesIO):
+    """A stream that never returns more than 8 bytes from a read."""
+
+    def read(self, size=-1):
+        return super().read(8 if size is None or size < 0 else min(size, 8))
+
+    def readinto(self, buffer):
+        return super().readinto(memoryview(buffer)[:8])
+
+
 class TestPyrage(unittest.TestCase):
     def test_encrypt_fails_with_no_receipients(self):
         with self.assertRaisesRegex(
@@ -136,6 +155,1025 @@ class TestPyrage(unittest.TestCase):
         for identity in identities:
             self.assertEqual(b"test matrix", pyrage.decrypt(encrypted, [identity]))
 
+    def test_armor_roundtrip(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()])
+        self.assertFalse(pyrage.is_armored(encrypted))
+
+        armored = pyrage.armor(encrypted)
+        self.assertTrue(pyrage.is_armored(armored))
+        self.assertEqual(pyrage.dearmor(armored), encrypted)
+        self.assertEqual(pyrage.decrypt(armored, [identity]), b"test")
+
+    def test_dearmor_fails_not_armored(self):
+        with self.assertRaisesRegex(ValueError, "input is not armored"):
+            pyrage.dearmor(b"age-encryption.org/v1")
+
+    @parameterized.expand(
+        [(False, "binary"), (True, "armored"), (False, "auto"), (True, "auto")]
+    )
+    def test_decrypt_format(self, armored, format):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()], armored=armored)
+
+        self.assertEqual(pyrage.decrypt(encrypted, [identity], format=format), b"test")
+
+        decrypted = BytesIO()
+        pyrage.decrypt_io(BytesIO(encrypted), decrypted, [identity], format=format)
+        self.assertEqual(decrypted.getvalue(), b"test")
+
+    @parameterized.expand([(False, "armored"), (True, "binary")])
+    def test_decrypt_format_mismatch(self, armored, format):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()], armored=armored)
+
+        with self.assertRaisesRegex(pyrage.DecryptError, f"expected an? {format}"):
+            pyrage.decrypt(encrypted, [identity], format=format)
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            infile = os.path.join(tempdir, "encrypted")
+            outfile = os.path.join(tempdir, "decrypted")
+            with open(infile, "wb") as file:
+                file.write(encrypted)
+
+            with self.assertRaisesRegex(pyrage.DecryptError, f"expected an? {format}"):
+                pyrage.decrypt_file(infile, outfile, [identity], format=format)
+            self.assertFalse(os.path.exists(outfile))
+
+    @parameterized.expand([(False, "binary"), (True, "armored"), (True, "auto")])
+    def test_decrypt_format_short_reads(self, armored, format):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()], armored=armored)
+        mismatch = {"binary": "armored", "armored": "binary"}.get(format)
+
+        decrypted = BytesIO()
+        pyrage.decrypt_io(ShortReads(encrypted), decrypted, [identity], format=format)
+        self.assertEqual(decrypted.getvalue(), b"test")
+
+        if mismatch is not None:
+            with self.assertRaisesRegex(pyrage.DecryptError, f"expected an? {mismatch}"):
+                pyrage.decrypt_io(
+                    ShortReads(encrypted), BytesIO(), [identity], format=mismatch
+                )
+
+    def test_decrypt_format_invalid(self):
+        with self.assertRaisesRegex(ValueError, "invalid format"):
+            pyrage.decrypt(b"", [], format="base64")
+
+    @unittest.skipIf(sys.platform == "win32", "no mode bits on Windows")
+    def test_file_mode(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            unencrypted = os.path.join(tempdir, "unencrypted")
+            encrypted = os.path.join(tempdir, "encrypted")
+            decrypted = os.path.join(tempdir, "decrypted")
+            with open(unencrypted, "wb") as file:
+                file.write(b"test")
+
+            pyrage.encrypt_file(unencrypted, encrypted, [identity.to_public()], mode=0o644)
+            pyrage.decrypt_file(encrypted, decrypted, [identity])
+
+            self.assertEqual(stat.S_IMODE(os.stat(encrypted).st_mode), 0o644)
+            self.assertEqual(stat.S_IMODE(os.stat(decrypted).st_mode), 0o600)
+
+    def test_file_no_overwrite(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            unencrypted = os.path.join(tempdir, "unencrypted")
+            encrypted = os.path.join(tempdir, "encrypted")
+            with open(unencrypted, "wb") as file:
+                file.write(b"test")
+            with open(encrypted, "wb") as file:
+                file.write(b"existing")
+
+            with self.assertRaises(FileExistsError):
+                pyrage.encrypt_file(
+                    unencrypted, encrypted, [identity.to_public()], overwrite=False
+                )
+            with open(encrypted, "rb") as file:
+                self.assertEqual(file.read(), b"existing")
+
+            pyrage.encrypt_file(unencrypted, encrypted, [identity.to_public()])
+            with open(encrypted, "rb") as file:
+                self.assertNotEqual(file.read(), b"existing")
+
+    def test_decrypt_file_failure_leaves_no_output(self):
+        alice = pyrage.x25519.Identity.generate()
+        bob = pyrage.x25519.Identity.generate()
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            encrypted = os.path.join(tempdir, "encrypted")
+            decrypted = os.path.join(tempdir, "decrypted")
+            with open(encrypted, "wb") as file:
+                file.write(pyrage.encrypt(b"test" * 100000, [alice.to_public()])[:-1])
+
+            with self.assertRaises(pyrage.DecryptError):
+                pyrage.decrypt_file(encrypted, decrypted, [bob])
+            with self.assertRaises(OSError):
+                pyrage.decrypt_file(encrypted, decrypted, [alice])
+            self.assertEqual(os.listdir(tempdir), ["encrypted"])
+
+    def test_decrypt_io_atomic(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test" * 100000, [identity.to_public()])
+
+        decrypted = BytesIO()
+        with self.assertRaises(OSError):
+            pyrage.decrypt_io(BytesIO(encrypted[:-1]), decrypted, [identity], atomic=True)
+        self.assertEqual(decrypted.getvalue(), b"")
+
+        pyrage.decrypt_io(BytesIO(encrypted), decrypted, [identity], atomic=True)
+        self.assertEqual(decrypted.getvalue(), b"test" * 100000)
+
+    @parameterized.expand([(1,), (4,)])
+    def test_decrypt_io_atomic_large(self, threads):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(17 * 1024 * 1024)
+        encrypted = pyrage.encrypt(plaintext, [identity.to_public()])
+
+        decrypted = BytesIO()
+        with self.assertRaises(OSError):
+            pyrage.decrypt_io(
+                BytesIO(encrypted[:-1]),
+                decrypted,
+                [identity],
+                atomic=True,
+                threads=threads,
+            )
+        self.assertEqual(decrypted.getvalue(), b"")
+
+        pyrage.decrypt_io(
+            BytesIO(encrypted), decrypted, [identity], atomic=True, threads=threads
+        )
+        self.assertEqual(decrypted.getvalue(), plaintext)
+
+    def test_roundtrip_file_pathlike(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            tempdir = Path(tempdir)
+            unencrypted = tempdir / "unencrypted"
+            unencrypted.write_bytes(b"test")
+
+            pyrage.encrypt_file(unencrypted, tempdir / "encrypted", [identity.to_public()])
+            pyrage.decrypt_file(
+                os.fsencode(tempdir / "encrypted"), tempdir / "decrypted", [identity]
+            )
+
+            self.assertEqual((tempdir / "decrypted").read_bytes(), b"test")
+
+    @unittest.skipIf(sys.platform in ("win32", "darwin"), "requires arbitrary filename bytes")
+    def test_roundtrip_file_non_utf8(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            tempdir = os.fsencode(tempdir)
+            unencrypted = os.path.join(tempdir, b"unencrypted-\xff")
+            encrypted = os.path.join(tempdir, b"encrypted-\xff")
+            decrypted = os.path.join(tempdir, b"decrypted-\xff")
+            with open(unencrypted, "wb") as file:
+                file.write(b"test")
+
+            pyrage.encrypt_file(unencrypted, encrypted, [identity.to_public()])
+            pyrage.decrypt_file(encrypted, decrypted, [identity])
+
+            with open(decrypted, "rb") as file:
+                self.assertEqual(file.read(), b"test")
+
+    def test_roundtrip_file_fd(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        with tempfile.TemporaryFile() as unencrypted, tempfile.TemporaryFile() as encrypted:
+            unencrypted.write(b"test")
+            unencrypted.flush()
+            unencrypted.seek(0)
+
+            pyrage.encrypt_file(
+                unencrypted.fileno(), encrypted.fileno(), [identity.to_public()]
+            )
+            encrypted.seek(0)
+
+            with tempfile.TemporaryDirectory() as tempdir:
+                decrypted = os.path.join(tempdir, "decrypted")
+                pyrage.decrypt_file(encrypted.fileno(), decrypted, [identity])
+                with open(decrypted, "rb") as file:
+                    self.assertEqual(file.read(), b"test")
+
+            # the descriptors are left open
+            os.fstat(unencrypted.fileno())
+            os.fstat(encrypted.fileno())
+
+    def test_file_progress(self):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(1024 * 1024)
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            unencrypted = os.path.join(tempdir, "unencrypted")
+            encrypted = os.path.join(tempdir, "encrypted")
+            decrypted = os.path.join(tempdir, "decrypted")
+            with open(unencrypted, "wb") as file:
+                file.write(plaintext)
+
+            calls = []
+            pyrage.encrypt_file(
+                unencrypted,
+                encrypted,
+                [identity.to_public()],
+                progress=lambda done, total: calls.append((done, total)),
+                progress_interval=256 * 1024,
+            )
+            self.assertGreaterEqual(len(calls), 4)
+            self.assertEqual(calls[-1], (len(plaintext), len(plaintext)))
+            self.assertEqual(calls, sorted(calls))
+
+            calls = []
+            size = os.path.getsize(encrypted)
+            pyrage.decrypt_file(
+                encrypted,
+                decrypted,
+                [identity],
+                progress=lambda done, total: calls.append((done, total)),
+            )
+            self.assertEqual(calls[-1], (size, size))
+
+    def test_io_progress(self):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = b"test" * 100000
+
+        calls = []
+        encrypted = BytesIO()
+        pyrage.encrypt_io(
+            BytesIO(plaintext),
+            encrypted,
+            [identity.to_public()],
+            progress=lambda done, total: calls.append((done, total)),
+        )
+        self.assertEqual(calls[-1], (len(plaintext), None))
+
+        calls = []
+        decrypted = BytesIO()
+        pyrage.decrypt_io(
+            BytesIO(encrypted.getvalue()),
+            decrypted,
+            [identity],
+            progress=lambda done, total: calls.append((done, total)),
+        )
+        self.assertEqual(calls[-1], (len(encrypted.getvalue()), None))
+        self.assertEqual(decrypted.getvalue(), plaintext)
+
+    def test_progress_error_propagates(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        def progress(done, total):
+            raise ZeroDivisionError
+
+        with self.assertRaises(ZeroDivisionError):
+            pyrage.encrypt_io(
+                BytesIO(b"test"), BytesIO(), [identity.to_public()], progress=progress
+            )
+
+    @parameterized.expand([(1,), (4,)])
+    def test_file_cancel_from_thread(self, threads):
+        identity = pyrage.x25519.Identity.generate()
+        token = pyrage.CancellationToken()
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            unencrypted = os.path.join(tempdir, "unencrypted")
+            with open(unencrypted, "wb") as file:
+                file.truncate(256 * 1024 * 1024)
+
+            # No Python code runs during the operation, so the timer can only
+            # fire if the GIL is released while it's running.
+            timer = threading.Timer(0.05, token.cancel)
+            timer.start()
+            try:
+                with self.assertRaises(pyrage.CancelledError):
+                    pyrage.encrypt_file(
+                        unencrypted,
+                        os.path.join(tempdir, "encrypted"),
+                        [identity.to_public()],
+                        cancel=token,
+                        threads=threads,
+                    )
+            finally:
+                timer.join()
+            self.assertTrue(token.cancelled)
+            self.assertEqual(os.listdir(tempdir), ["unencrypted"])
+
+    @unittest.skipIf(sys.platform == "win32", "requires SIGINT delivery")
+    def test_file_keyboard_interrupt(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            unencrypted = os.path.join(tempdir, "unencrypted")
+            with open(unencrypted, "wb") as file:
+                file.write(os.urandom(1024 * 1024))
+
+            with self.assertRaises(KeyboardInterrupt):
+                pyrage.encrypt_file(
+                    unencrypted,
+                    os.path.join(tempdir, "encrypted"),
+                    [identity.to_public()],
+                    progress=lambda done, total: signal.raise_signal(signal.SIGINT),
+                    progress_interval=64 * 1024,
+                )
+            self.assertEqual(os.listdir(tempdir), ["unencrypted"])
+
+    @parameterized.expand(
+        [(0,), (1,), (64 * 1024,), (64 * 1024 * 32,), (64 * 1024 * 32 + 1,)]
+    )
+    def test_threads_interop(self, size):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(size)
+
+        encrypted = BytesIO()
+        pyrage.encrypt_io(BytesIO(plaintext), encrypted, [identity.to_public()], threads=2)
+        self.assertEqual(pyrage.decrypt(encrypted.getvalue(), [identity]), plaintext)
+
+        encrypted = pyrage.encrypt(plaintext, [identity.to_public()], armored=True)
+        decrypted = BytesIO()
+        pyrage.decrypt_io(BytesIO(encrypted), decrypted, [identity], threads=3)
+        self.assertEqual(decrypted.getvalue(), plaintext)
+
+    def test_threads_file(self):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(3 * 1024 * 1024 + 5)
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            unencrypted = os.path.join(tempdir, "unencrypted")
+            encrypted = os.path.join(tempdir, "encrypted")
+            decrypted = os.path.join(tempdir, "decrypted")
+            with open(unencrypted, "wb") as file:
+                file.write(plaintext)
+
+            pyrage.encrypt_file(unencrypted, encrypted, [identity.to_public()], threads=4)
+            pyrage.decrypt_file(encrypted, decrypted, [identity], threads=4)
+
+            with open(decrypted, "rb") as file:
+                self.assertEqual(file.read(), plaintext)
+
+    def test_threads_rejects_damage(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(os.urandom(200000), [identity.to_public()])
+
+        flipped = encrypted[:-1] + bytes([encrypted[-1] ^ 1])
+        for damaged in (encrypted[:-1], encrypted + b"x", flipped):
+            with self.assertRaisesRegex(OSError, "decryption error"):
+                pyrage.decrypt_io(BytesIO(damaged), BytesIO(), [identity], threads=2)
+
+        # drop the final (partial) chunk entirely
+        truncated = encrypted[: len(encrypted) - (200000 % (64 * 1024)) - 16]
+        with self.assertRaisesRegex(OSError, "truncated"):
+            pyrage.decrypt_io(BytesIO(truncated), BytesIO(), [identity], threads=2)
+
+        with self.assertRaisesRegex(pyrage.DecryptError, "No matching keys found"):
+            pyrage.decrypt_io(
+                BytesIO(encrypted), BytesIO(), [pyrage.x25519.Identity.generate()], threads=2
+            )
+
+    def test_threads_invalid(self):
+        identity = pyrage.x25519.Identity.generate()
+        with self.assertRaisesRegex(ValueError, "threads must be at least 1"):
+            pyrage.encrypt_io(BytesIO(b"test"), BytesIO(), [identity.to_public()], threads=0)
+
+    def test_decrypt_return_info(self):
+        alice = pyrage.x25519.Identity.generate()
+        pubkey, privkey = ssh_keypair("ed25519")
+        bob = pyrage.ssh.Identity.from_buffer(privkey.encode())
+        encrypted = pyrage.encrypt(
+            b"test", [alice.to_public(), pyrage.ssh.Recipient.from_str(pubkey)]
+        )
+
+        self.assertEqual(pyrage.decrypt(encrypted, [alice]), b"test")
+
+        decrypted, info = pyrage.decrypt(encrypted, [alice, bob], return_info=True)
+        self.assertEqual(decrypted, b"test")
+        self.assertEqual(info.identity_index, 0)
+        self.assertEqual(info.stanza_tag, "X25519")
+        # one stanza per recipient, plus one of grease
+        self.assertEqual(info.stanza_count, 3)
+
+        other = pyrage.x25519.Identity.generate()
+        _, info = pyrage.decrypt(encrypted, [other, bob], return_info=True)
+        self.assertEqual(info.identity_index, 1)
+        self.assertEqual(info.stanza_tag, "ssh-ed25519")
+        self.assertIn("ssh-ed25519", repr(info))
+
+    def test_decrypt_output_type(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()])
+
+        decrypted = pyrage.decrypt(encrypted, [identity], output_type="bytearray")
+        self.assertIsInstance(decrypted, bytearray)
+        self.assertEqual(decrypted, b"test")
+
+        buf, info = pyrage.decrypt(
+            encrypted, [identity], output_type="secret", return_info=True
+        )
+        self.assertIsInstance(buf, pyrage.SecretBuffer)
+        self.assertEqual(info.identity_index, 0)
+        self.assertEqual(len(buf), 4)
+        self.assertEqual(repr(buf), "SecretBuffer(<4 bytes>)")
+        self.assertEqual(buf.reveal(), bytearray(b"test"))
+        self.assertFalse(buf.closed)
+
+        buf.close()
+        self.assertTrue(buf.closed)
+        self.assertEqual(repr(buf), "SecretBuffer(<closed>)")
+        with self.assertRaisesRegex(ValueError, "SecretBuffer is closed"):
+            buf.reveal()
+
+        with pyrage.decrypt(encrypted, [identity], output_type="secret") as buf:
+            self.assertEqual(buf.reveal(), b"test")
+        self.assertTrue(buf.closed)
+
+        with self.assertRaisesRegex(ValueError, "invalid output_type"):
+            pyrage.decrypt(encrypted, [identity], output_type="str")
+
+    @parameterized.expand([(False,), (True,)])
+    def test_buffer_inputs(self, armored):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(200 * 1024 + 3)
+        encrypted = pyrage.encrypt(
+            bytearray(plaintext), [identity.to_public()], armored=armored
+        )
+        self.assertEqual(pyrage.decrypt(encrypted, [identity]), plaintext)
+
+        for ciphertext in (
+            bytearray(encrypted),
+            memoryview(encrypted),
+            memoryview(b"junk" + encrypted)[4:],
+            array.array("B", encrypted),
+        ):
+            self.assertEqual(pyrage.decrypt(ciphertext, [identity]), plaintext)
+
+        with tempfile.TemporaryFile() as f:
+            f.write(encrypted)
+            f.flush()
+            with mmap.mmap(f.fileno(), 0, access=mmap.ACCESS_READ) as mapped:
+                self.assertEqual(pyrage.decrypt(mapped, [identity]), plaintext)
+
+        # typed buffers are encrypted as their raw bytes
+        numbers = array.array("I", range(1000))
+        encrypted = pyrage.encrypt(numbers, [identity.to_public()])
+        self.assertEqual(pyrage.decrypt(encrypted, [identity]), numbers.tobytes())
+
+        with self.assertRaisesRegex(TypeError, "bytes-like object is required"):
+            pyrage.encrypt("not bytes", [identity.to_public()])
+
+    @parameterized.expand([(False,), (True,)])
+    def test_decrypt_into(self, armored):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(200 * 1024 + 3)
+        encrypted = pyrage.encrypt(plaintext, [identity.to_public()], armored=armored)
+
+        out = bytearray(len(plaintext) + 10)
+        n = pyrage.decrypt_into(memoryview(encrypted), out, [identity])
+        self.assertEqual(n, len(plaintext))
+        self.assertEqual(out[:n], plaintext)
+        self.assertEqual(out[n:], bytes(10))
+
+        with tempfile.TemporaryFile() as f:
+            f.truncate(len(plaintext))
+            with mmap.mmap(f.fileno(), len(plaintext)) as mapped:
+                self.assertEqual(pyrage.decrypt_into(encrypted, mapped, [identity]), n)
+                self.assertEqual(mapped[:], plaintext)
+
+    def test_decrypt_into_fails_cleanly(self):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(200 * 1024)
+        encrypted = pyrage.encrypt(plaintext, [identity.to_public()])
+
+        out = bytearray(len(plaintext) - 1)
+        with self.assertRaisesRegex(ValueError, "output buffer is too small"):
+            pyrage.decrypt_into(encrypted, out, [identity])
+        self.assertEqual(out, bytes(len(out)))
+
+        out = bytearray(len(plaintext))
+        with self.assertRaisesRegex(pyrage.DecryptError, "decryption error"):
+            pyrage.decrypt_into(encrypted[:-100], out, [identity])
+        self.assertEqual(out, bytes(len(out)))
+
+        with self.assertRaisesRegex(pyrage.DecryptError, "No matching keys found"):
+            pyrage.decrypt_into(encrypted, out, [pyrage.x25519.Identity.generate()])
+
+        with self.assertRaisesRegex(TypeError, "expected a writable buffer"):
+            pyrage.decrypt_into(encrypted, bytes(len(plaintext)), [identity])
+
+    def test_strings(self):
+        alice = pyrage.x25519.Identity.generate()
+        pubkey, privkey = ssh_keypair("ed25519")
+        rsa_pubkey, rsa_privkey = ssh_keypair("rsa2048")
+
+        encrypted = pyrage.encrypt(
+            b"test", [str(alice.to_public()), pubkey, rsa_pubkey.strip()]
+        )
+        for identity in (alice.reveal_secret(), privkey, rsa_privkey):
+            self.assertEqual(pyrage.decrypt(encrypted, [identity]), b"test")
+
+        # strings and objects mix freely
+        encrypted = pyrage.encrypt(b"test", [alice.to_public(), pubkey])
+        self.assertEqual(
+            pyrage.decrypt(encrypted, [pyrage.x25519.Identity.generate(), privkey]), b"test"
+        )
+
+    def test_parse_recipient_identity(self):
+        alice = pyrage.x25519.Identity.generate()
+        pubkey, privkey = ssh_keypair("ed25519")
+
+        recipient = pyrage.parse_recipient(str(alice.to_public()))
+        self.assertIsInstance(recipient, pyrage.x25519.Recipient)
+        self.assertEqual(str(recipient), str(alice.to_public()))
+        self.assertIsInstance(pyrage.parse_recipient(pubkey), pyrage.ssh.Recipient)
+
+        identity = pyrage.parse_identity(alice.reveal_secret())
+        self.assertIsInstance(identity, pyrage.x25519.Identity)
+        self.assertEqual(identity.reveal_secret(), alice.reveal_secret())
+        self.assertIsInstance(pyrage.parse_identity(privkey), pyrage.ssh.Identity)
+
+        with self.assertRaisesRegex(pyrage.RecipientError, "invalid recipient: nope"):
+            pyrage.parse_recipient("nope")
+        with self.assertRaisesRegex(pyrage.RecipientError, "invalid recipient: nope"):
+            pyrage.encrypt(b"test", ["nope"])
+
+        # identities are secret, so errors don't repeat them
+        secret = alice.reveal_secret()[:-1]
+        with self.assertRaisesRegex(pyrage.IdentityError, "^invalid identity$"):
+            pyrage.parse_identity(secret)
+        with self.assertRaisesRegex(pyrage.IdentityError, "^invalid identity$"):
+            pyrage.decrypt(b"", [secret])
+
+        with self.assertRaisesRegex(TypeError, "not a str"):
+            pyrage.encrypt(b"test", str(alice.to_public()))
+
+    def test_encrypt_armored_str(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt_armored(b"test", [identity.to_public()])
+        self.assertIsInstance(encrypted, str)
+        self.assertTrue(encrypted.startswith("-----BEGIN AGE ENCRYPTED FILE-----"))
+
+        self.assertEqual(pyrage.decrypt(encrypted, [identity]), b"test")
+        # as pasted into YAML, chat, etc.
+        pasted = f"\n  {encrypted}\n\n"
+        self.assertEqual(pyrage.decrypt(pasted, [identity], format="armored"), b"test")
+
+        with self.assertRaisesRegex(pyrage.DecryptError, "the input is text"):
+            pyrage.decrypt(encrypted, [identity], format="binary")
+        with self.assertRaisesRegex(pyrage.DecryptError, "the input is not armored"):
+            pyrage.decrypt("age-encryption.org/v1", [identity])
+
+    @parameterized.expand([(1,), (2,)])
+    def test_text_io(self, threads):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(200 * 1024)
+
+        armored = StringIO()
+        pyrage.encrypt_io(
+            BytesIO(plaintext), armored, [identity.to_public()], armored=True, threads=threads
+        )
+        self.assertTrue(pyrage.is_armored(armored.getvalue().encode()))
+
+        armored.seek(0)
+        decrypted = BytesIO()
+        pyrage.decrypt_io(armored, decrypted, [identity], threads=threads)
+        self.assertEqual(decrypted.getvalue(), plaintext)
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            path = Path(tempdir) / "encrypted.txt"
+            with path.open("w") as io:
+                pyrage.encrypt_io(BytesIO(b"test"), io, [identity.to_public()], armored=True)
+            with path.open() as io:
+                decrypted = BytesIO()
+                pyrage.decrypt_io(io, decrypted, [identity])
+                self.assertEqual(decrypted.getvalue(), b"test")
+
+    def test_text_io_requires_armor(self):
+        identity = pyrage.x25519.Identity.generate()
+        with self.assertRaisesRegex(TypeError, "requires armored=True"):
+            pyrage.encrypt_io(BytesIO(b"test"), StringIO(), [identity.to_public()])
+
+        armored = StringIO(pyrage.encrypt_armored(b"test", [identity.to_public()]))
+        with self.assertRaisesRegex(pyrage.DecryptError, "the input is text"):
+            pyrage.decrypt_io(armored, BytesIO(), [identity], format="binary")
+        with self.assertRaisesRegex(TypeError, "can't write decrypted output to a text stream"):
+            pyrage.decrypt_io(armored, StringIO(), [identity])
+
+    @parameterized.expand([(1,), (2,)])
+    def test_decrypt_file_io_return_info(self, threads):
+        alice = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [alice.to_public()])
+
+        info = pyrage.decrypt_io(
+            BytesIO(encrypted), BytesIO(), [alice], threads=threads, return_info=True
+        )
+        self.assertEqual((info.identity_index, info.stanza_tag), (0, "X25519"))
+        info = pyrage.decrypt_io(
+            BytesIO(encrypted), BytesIO(), [alice], atomic=True, return_info=True
+        )
+        self.assertEqual(info.stanza_count, 2)
+        self.assertIsNone(pyrage.decrypt_io(BytesIO(encrypted), BytesIO(), [alice]))
+
+        with tempfile.TemporaryDirectory() as tempdir:
+            path = Path(tempdir) / "encrypted"
+            path.write_bytes(encrypted)
+            info = pyrage.decrypt_file(
+                path, Path(tempdir) / "decrypted", [alice], threads=threads, return_info=True
+            )
+            self.assertEqual(info.identity_index, 0)
+
+    def test_decrypt_no_match_diagnostics(self):
+        pubkey, _ = ssh_keypair("ed25519")
+        recipient = pyrage.ssh.Recipient.from_str(pubkey)
+        encrypted = pyrage.encrypt(b"test", [recipient])
+        _, privkey = ssh_keypair("rsa2048")
+        identities = [
+            pyrage.x25519.Identity.generate(),
+            pyrage.ssh.Identity.from_buffer(privkey.encode()),
+        ]
+
+        with self.assertRaises(pyrage.DecryptError) as cm:
+            pyrage.decrypt(encrypted, identities)
+        message = str(cm.exception)
+        self.assertIn("No matching keys found", message)
+        self.assertIn("encrypted to 2 stanza(s)", message)
+        self.assertRegex(message, r"\n  ssh-ed25519 \S+ \S+\n")
+        self.assertIn("Identities tried: pyrage.x25519.Identity, pyrage.ssh.Identity", message)
+
+        for threads in (1, 2):
+            with self.assertRaisesRegex(pyrage.DecryptError, "ssh-ed25519"):
+                pyrage.decrypt_io(BytesIO(encrypted), BytesIO(), identities, threads=threads)
+
+        with self.assertRaisesRegex(pyrage.DecryptError, "Identities tried: none"):
+            pyrage.decrypt(encrypted, [])
+
+    @unittest.skipUnless(
+        sysconfig.get_config_var("Py_GIL_DISABLED"), "requires a free-threaded build"
+    )
+    def test_import_keeps_gil_disabled(self):
+        self.assertFalse(sys._is_gil_enabled())
+
+    @parameterized.expand([("x25519",), ("ssh",)])
+    def test_identity_shared_across_threads(self, kind):
+        if kind == "x25519":
+            identity = pyrage.x25519.Identity.generate()
+            recipient = identity.to_public()
+        else:
+            pubkey, privkey = ssh_keypair("ed25519")
+            identity = pyrage.ssh.Identity.from_buffer(privkey.encode())
+            recipient = pyrage.ssh.Recipient.from_str(pubkey)
+
+        plaintexts = [os.urandom(1024 + i) for i in range(64)]
+        ciphertexts = [pyrage.encrypt(p, [recipient]) for p in plaintexts]
+
+        workers = 8
+        barrier = threading.Barrier(workers)
+
+        def decrypt(ciphertext):
+            # Line every worker up first, so that the decryptions overlap.
+            try:
+                barrier.wait(timeout=5)
+            except threading.BrokenBarrierError:
+                pass
+            return pyrage.decrypt(ciphertext, [identity])
+
+        with ThreadPoolExecutor(max_workers=workers) as pool:
+            decrypted = list(pool.map(decrypt, ciphertexts))
+
+        self.assertEqual(plaintexts, decrypted)
+
+    @parameterized.expand([(False,), (True,)])
+    def test_encrypt_iter_roundtrip(self, armored):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(200_000)
+        chunks = [plaintext[i : i + 1000] for i in range(0, len(plaintext), 1000)]
+
+        encrypted = b"".join(
+            pyrage.encrypt_iter(iter(chunks), [identity.to_public()], armored=armored)
+        )
+        self.assertEqual(pyrage.decrypt(encrypted, [identity]), plaintext)
+
+    def test_encrypt_iter_streams(self):
+        identity = pyrage.x25519.Identity.generate()
+        consumed = []
+
+        def chunks():
+            for _ in range(4):
+                consumed.append(1)
+                yield bytearray(50_000)
+
+        encrypted = pyrage.encrypt_iter(chunks(), [identity.to_public()])
+        # The header comes out before any input is read, and the first STREAM
+        # chunk as soon as there's more than 64 KiB of it.
+        self.assertTrue(next(encrypted).startswith(b"age-encryption.org/v1"))
+        self.assertEqual(len(consumed), 0)
+        self.assertEqual(len(next(encrypted)), 64 * 1024 + 16)
+        self.assertEqual(len(consumed), 2)
+
+        rest = b"".join(encrypted)
+        self.assertEqual(len(consumed), 4)
+        self.assertEqual(list(encrypted), [])
+        self.assertTrue(rest)
+
+    def test_encrypt_iter_source_error(self):
+        identity = pyrage.x25519.Identity.generate()
+
+        def chunks():
+            yield b"x" * 100_000
+            raise RuntimeError("upstream failed")
+
+        encrypted = pyrage.encrypt_iter(chunks(), [identity.to_public()])
+        output = [next(encrypted), next(encrypted)]
+        with self.assertRaisesRegex(RuntimeError, "upstream failed"):
+            next(encrypted)
+        # The file is never finished, so it can't pass for a whole one.
+        self.assertEqual(list(encrypted), [])
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.decrypt(b"".join(output), [identity])
+
+    def test_encrypt_iter_fails_with_no_recipients(self):
+        with self.assertRaisesRegex(
+            pyrage.EncryptError, "expected at least one recipient"
+        ):
+            pyrage.encrypt_iter([b"test"], [])
+
+    def test_decrypt_iter(self):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(200_000)
+        encrypted = pyrage.encrypt(plaintext, [identity.to_public()])
+
+        consumed = []
+
+        def chunks():
+            for i in range(0, len(encrypted), 7_000):
+                consumed.append(i)
+                yield memoryview(encrypted)[i : i + 7_000]
+
+        decrypted = pyrage.decrypt_iter(chunks(), [identity])
+        first = next(decrypted)
+        self.assertEqual(len(first), 64 * 1024)
+        self.assertLess(len(consumed), len(range(0, len(encrypted), 7_000)))
+
+        self.assertEqual(first + b"".join(decrypted), plaintext)
+        self.assertEqual(list(decrypted), [])
+
+    def test_decrypt_iter_armored(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()], armored=True)
+        chunks = [encrypted[i : i + 10] for i in range(0, len(encrypted), 10)]
+
+        self.assertEqual(b"".join(pyrage.decrypt_iter(chunks, [identity])), b"test")
+
+    def test_decrypt_iter_format(self):
+        identity = pyrage.x25519.Identity.generate()
+        armored = pyrage.encrypt(b"test", [identity.to_public()], armored=True)
+        # chunks shorter than the armor marker
+        chunks = [armored[i : i + 10] for i in range(0, len(armored), 10)]
+
+        decrypted = pyrage.decrypt_iter(chunks, [identity], format="armored")
+        self.assertEqual(b"".join(decrypted), b"test")
+        with self.assertRaisesRegex(pyrage.DecryptError, "expected a binary"):
+            pyrage.decrypt_iter(chunks, [identity], format="binary")
+        with self.assertRaisesRegex(ValueError, "invalid format"):
+            pyrage.decrypt_iter(chunks, [identity], format="base64")
+
+    def test_decrypt_iter_no_match(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()])
+
+        # This fails on the call, before any output.
+        with self.assertRaisesRegex(pyrage.DecryptError, "No matching keys found"):
+            pyrage.decrypt_iter([encrypted], [pyrage.x25519.Identity.generate()])
+
+    def test_decrypt_iter_truncated(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(os.urandom(100_000), [identity.to_public()])
+
+        decrypted = pyrage.decrypt_iter([encrypted[:-100]], [identity])
+        self.assertEqual(len(next(decrypted)), 64 * 1024)
+        with self.assertRaises(pyrage.DecryptError):
+            next(decrypted)
+        self.assertEqual(list(decrypted), [])
+
+    def test_decrypt_iter_source_error(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(os.urandom(100_000), [identity.to_public()])
+
+        def chunks():
+            yield encrypted[:1000]
+            raise RuntimeError("upstream failed")
+
+        decrypted = pyrage.decrypt_iter(chunks(), [identity])
+        with self.assertRaisesRegex(RuntimeError, "upstream failed"):
+            next(decrypted)
+
+    def test_iter_invalid_chunk(self):
+        identity = pyrage.x25519.Identity.generate()
+        with self.assertRaises(TypeError):
+            list(pyrage.encrypt_iter(["not bytes"], [identity.to_public()]))
+
+    def test_limits_header(self):
+        identity = pyrage.x25519.Identity.generate()
+        recipients = [pyrage.x25519.Identity.generate().to_public() for _ in range(9)]
+        encrypted = pyrage.encrypt(b"test", [identity.to_public(), *recipients])
+
+        self.assertTrue(issubclass(pyrage.LimitExceededError, pyrage.DecryptError))
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_stanzas"):
+            pyrage.decrypt(encrypted, [identity], max_stanzas=5)
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_header_size"):
+            pyrage.decrypt(encrypted, [identity], max_header_size=200)
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_stanzas"):
+            pyrage.decrypt_iter([encrypted], [identity], max_stanzas=5)
+
+        # Armor is removed first, so the limits apply to the same header.
+        armored = pyrage.armor(encrypted)
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_header_size"):
+            pyrage.decrypt(armored, [identity], max_header_size=200)
+
+        decrypted = pyrage.decrypt(
+            encrypted, [identity], max_stanzas=20, max_header_size=4096
+        )
+        self.assertEqual(decrypted, b"test")
+
+    def test_limits_header_unterminated_line(self):
+        identity = pyrage.x25519.Identity.generate()
+        malicious = b"age-encryption.org/v1\n-> X25519 " + b"A" * 1_000_000
+
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_header_size"):
+            pyrage.decrypt(malicious, [identity], max_header_size=1024)
+
+    def test_limits_plaintext(self):
+        identity = pyrage.x25519.Identity.generate()
+        plaintext = os.urandom(200_000)
+        encrypted = pyrage.encrypt(plaintext, [identity.to_public()])
+
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_plaintext_size"):
+            pyrage.decrypt(encrypted, [identity], max_plaintext_size=100_000)
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_plaintext_size"):
+            pyrage.decrypt_into(
+                encrypted,
+                bytearray(len(plaintext)),
+                [identity],
+                max_plaintext_size=100_000,
+            )
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_plaintext_size"):
+            list(pyrage.decrypt_iter([encrypted], [identity], max_plaintext_size=100_000))
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_plaintext_size"):
+            pyrage.decrypt_io(
+                BytesIO(encrypted), BytesIO(), [identity], max_plaintext_size=100_000
+            )
+
+        self.assertEqual(
+            pyrage.decrypt(encrypted, [identity], max_plaintext_size=len(plaintext)),
+            plaintext,
+        )
+
+    @parameterized.expand([(1,), (4,)])
+    def test_limits_plaintext_file(self, threads):
+        identity = pyrage.x25519.Identity.generate()
+        with tempfile.TemporaryDirectory() as tempdir:
+            source = Path(tempdir) / "source.age"
+            destination = Path(tempdir) / "destination"
+            source.write_bytes(
+                pyrage.encrypt(os.urandom(500_000), [identity.to_public()])
+            )
+
+            with self.assertRaisesRegex(
+                pyrage.LimitExceededError, "max_plaintext_size"
+            ):
+                pyrage.decrypt_file(
+                    source,
+                    destination,
+                    [identity],
+                    threads=threads,
+                    max_plaintext_size=100_000,
+                )
+            self.assertFalse(destination.exists())
+
+
+    def test_can_decrypt(self):
+        alice = pyrage.x25519.Identity.generate()
+        bob = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(b"test", [bob.to_public()])
+
+        self.assertTrue(pyrage.can_decrypt(encrypted, [alice, bob]))
+        self.assertFalse(pyrage.can_decrypt(encrypted, [alice]))
+        self.assertEqual(
+            pyrage.can_decrypt(encrypted, [alice, bob], return_index=True), 1
+        )
+        self.assertEqual(pyrage.can_decrypt(encrypted, [alice], return_index=True), -1)
+        self.assertTrue(pyrage.can_decrypt(pyrage.armor(encrypted), [bob]))
+
+    def test_can_decrypt_path(self):
+        identity = pyrage.x25519.Identity.generate()
+        with tempfile.TemporaryDirectory() as tempdir:
+            path = Path(tempdir) / "test.age"
+            path.write_bytes(pyrage.encrypt(b"test", [identity.to_public()]))
+
+            self.assertTrue(pyrage.can_decrypt(path, [identity]))
+            self.assertTrue(pyrage.can_decrypt(str(path), [identity]))
+            with path.open("rb") as f:
+                self.assertTrue(pyrage.can_decrypt(f.fileno(), [identity]))
+
+    def test_can_decrypt_ignores_payload(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = bytearray(pyrage.encrypt(b"x" * 1000, [identity.to_public()]))
+        encrypted[-1] ^= 1
+
+        self.assertTrue(pyrage.can_decrypt(encrypted, [identity]))
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.verify(encrypted, [identity])
+
+    def test_can_decrypt_tampered_header(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = bytearray(pyrage.encrypt(b"test", [identity.to_public()]))
+        mac = encrypted.index(b"\n--- ") + len(b"\n--- ")
+        encrypted[mac] = ord("B") if encrypted[mac] == ord("A") else ord("A")
+
+        with self.assertRaisesRegex(pyrage.DecryptError, "MAC"):
+            pyrage.can_decrypt(encrypted, [identity])
+
+    def test_can_decrypt_limits(self):
+        identity = pyrage.x25519.Identity.generate()
+        recipients = [pyrage.x25519.Identity.generate().to_public() for _ in range(9)]
+        encrypted = pyrage.encrypt(b"test", [identity.to_public(), *recipients])
+
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_stanzas"):
+            pyrage.can_decrypt(encrypted, [identity], max_stanzas=5)
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_header_size"):
+            pyrage.can_decrypt(pyrage.armor(encrypted), [identity], max_header_size=200)
+        self.assertTrue(
+            pyrage.can_decrypt(encrypted, [identity], max_stanzas=20, max_header_size=4096)
+        )
+
+    def test_verify(self):
+        alice = pyrage.x25519.Identity.generate()
+        bob = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(os.urandom(200_000), [bob.to_public()])
+
+        self.assertIsNone(pyrage.verify(encrypted, [bob]))
+        info = pyrage.verify(encrypted, [alice, bob], return_info=True)
+        self.assertEqual(info.identity_index, 1)
+        with self.assertRaisesRegex(pyrage.DecryptError, "No matching keys"):
+            pyrage.verify(encrypted, [alice])
+
+    def test_verify_truncated(self):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(os.urandom(200_000), [identity.to_public()])
+
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.verify(encrypted[: -70_000], [identity])
+
+    def test_verify_limits(self):
+        identity = pyrage.x25519.Identity.generate()
+        recipients = [pyrage.x25519.Identity.generate().to_public() for _ in range(9)]
+        encrypted = pyrage.encrypt(
+            os.urandom(200_000), [identity.to_public(), *recipients]
+        )
+
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_stanzas"):
+            pyrage.verify(encrypted, [identity], max_stanzas=5)
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_header_size"):
+            pyrage.verify(encrypted, [identity], max_header_size=200)
+        for threads in (1, 4):
+            with self.assertRaisesRegex(pyrage.LimitExceededError, "max_plaintext_size"):
+                pyrage.verify(
+                    encrypted, [identity], threads=threads, max_plaintext_size=100_000
+                )
+        self.assertIsNone(
+            pyrage.verify(
+                encrypted,
+                [identity],
+                max_plaintext_size=200_000,
+                max_header_size=4096,
+                max_stanzas=20,
+            )
+        )
+
+    @parameterized.expand([(1,), (4,)])
+    def test_verify_path(self, threads):
+        identity = pyrage.x25519.Identity.generate()
+        encrypted = pyrage.encrypt(os.urandom(500_000), [identity.to_public()])
+        with tempfile.TemporaryDirectory() as tempdir:
+            path = Path(tempdir) / "test.age"
+            path.write_bytes(encrypted)
+
+            seen = []
+            pyrage.verify(
+                path,
+                [identity],
+                threads=threads,
+                progress=lambda done, total: seen.append((done, total)),
+            )
+            self.assertEqual(seen[-1], (len(encrypted), len(encrypted)))
+
+            corrupted = bytearray(encrypted)
+            corrupted[len(corrupted) // 2] ^= 1
+            path.write_bytes(corrupted)
+            with self.assertRaises(pyrage.DecryptError):
+                pyrage.verify(path, [identity], threads=threads)
+
 
 if __name__ == "__main__":
     unittest.main()
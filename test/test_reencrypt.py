@@ -0,0 +1,133 @@
+import io
+import tempfile
+import unittest
+from pathlib import Path
+
+import pyrage
+
+
+class TestReencrypt(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.TemporaryDirectory()
+        self.root = Path(self.tempdir.name)
+        self.old = pyrage.x25519.Identity.generate()
+        self.new = pyrage.x25519.Identity.generate()
+        self.plaintext = b"test" * 100_000
+        self.encrypted = pyrage.encrypt(self.plaintext, [self.old.to_public()])
+        self.infile = self.root / "in.age"
+        self.outfile = self.root / "out.age"
+        self.infile.write_bytes(self.encrypted)
+
+    def tearDown(self):
+        self.tempdir.cleanup()
+
+    def test_reencrypt_file(self):
+        pyrage.reencrypt_file(self.infile, self.outfile, [self.old], [self.new.to_public()])
+
+        encrypted = self.outfile.read_bytes()
+        self.assertEqual(pyrage.decrypt(encrypted, [self.new]), self.plaintext)
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.decrypt(encrypted, [self.old])
+
+    def test_reencrypt_file_in_place(self):
+        pyrage.reencrypt_file(
+            self.infile, self.infile, [self.old], [self.new.to_public()], armored=True
+        )
+
+        encrypted = self.infile.read_bytes()
+        self.assertTrue(pyrage.is_armored(encrypted))
+        self.assertEqual(pyrage.decrypt(encrypted, [self.new]), self.plaintext)
+
+    def test_reencrypt_file_progress(self):
+        calls = []
+        pyrage.reencrypt_file(
+            self.infile,
+            self.outfile,
+            [self.old],
+            [self.new.to_public()],
+            progress=lambda done, total: calls.append((done, total)),
+        )
+        self.assertEqual(calls[-1], (len(self.encrypted), len(self.encrypted)))
+
+    def test_reencrypt_file_wrong_identity(self):
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.reencrypt_file(
+                self.infile, self.outfile, [self.new], [self.new.to_public()]
+            )
+        self.assertFalse(self.outfile.exists())
+
+    def test_reencrypt_file_damaged(self):
+        damaged = bytearray(self.encrypted)
+        damaged[-1] ^= 1
+        self.infile.write_bytes(damaged)
+
+        with self.assertRaises(OSError):
+            pyrage.reencrypt_file(
+                self.infile, self.outfile, [self.old], [self.new.to_public()]
+            )
+        self.assertFalse(self.outfile.exists())
+
+    def test_reencrypt_file_format(self):
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.reencrypt_file(
+                self.infile,
+                self.outfile,
+                [self.old],
+                [self.new.to_public()],
+                format="armored",
+            )
+
+    def test_reencrypt_file_limits(self):
+        for limit in (
+            {"max_plaintext_size": 100_000},
+            {"max_header_size": 50},
+            {"max_stanzas": 0},
+        ):
+            with self.subTest(limit=limit):
+                with self.assertRaisesRegex(pyrage.LimitExceededError, next(iter(limit))):
+                    pyrage.reencrypt_file(
+                        self.infile, self.outfile, [self.old], [self.new.to_public()], **limit
+                    )
+                self.assertFalse(self.outfile.exists())
+
+        pyrage.reencrypt_file(
+            self.infile,
+            self.outfile,
+            [self.old],
+            [self.new.to_public()],
+            max_plaintext_size=len(self.plaintext),
+            max_header_size=4096,
+            max_stanzas=5,
+        )
+        self.assertEqual(pyrage.decrypt(self.outfile.read_bytes(), [self.new]), self.plaintext)
+
+    def test_reencrypt_io(self):
+        writer = io.BytesIO()
+        pyrage.reencrypt_io(
+            io.BytesIO(self.encrypted), writer, [self.old], [self.new.to_public()]
+        )
+        self.assertEqual(pyrage.decrypt(writer.getvalue(), [self.new]), self.plaintext)
+
+    def test_reencrypt_io_limits(self):
+        for limit in (
+            {"max_plaintext_size": 100_000},
+            {"max_header_size": 50},
+            {"max_stanzas": 0},
+        ):
+            with self.subTest(limit=limit):
+                with self.assertRaisesRegex(pyrage.LimitExceededError, next(iter(limit))):
+                    pyrage.reencrypt_io(
+                        io.BytesIO(self.encrypted),
+                        io.BytesIO(),
+                        [self.old],
+                        [self.new.to_public()],
+                        **limit,
+                    )
+
+    def test_reencrypt_io_no_recipients(self):
+        with self.assertRaises(pyrage.EncryptError):
+            pyrage.reencrypt_io(io.BytesIO(self.encrypted), io.BytesIO(), [self.old], [])
+
+
+if __name__ == "__main__":
+    unittest.main()
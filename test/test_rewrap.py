@@ -0,0 +1,219 @@
+import tempfile
+import unittest
+from pathlib import Path
+
+import pyrage
+
+
+class TestRewrap(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.TemporaryDirectory()
+        self.root = Path(self.tempdir.name)
+        self.old = pyrage.x25519.Identity.generate()
+        self.new = pyrage.x25519.Identity.generate()
+        self.infile = self.root / "in.age"
+        self.outfile = self.root / "out.age"
+        self.infile.write_bytes(pyrage.encrypt(b"test" * 1000, [self.old.to_public()]))
+
+    def tearDown(self):
+        self.tempdir.cleanup()
+
+    def test_add_recipient(self):
+        pyrage.rewrap(self.infile, self.outfile, [self.old], [self.new.to_public()])
+
+        encrypted = self.outfile.read_bytes()
+        self.assertEqual(pyrage.decrypt(encrypted, [self.old]), b"test" * 1000)
+        self.assertEqual(pyrage.decrypt(encrypted, [self.new]), b"test" * 1000)
+
+    def test_payload_unchanged(self):
+        pyrage.rewrap(self.infile, self.outfile, [self.old], [self.new.to_public()])
+
+        before = self.infile.read_bytes()
+        after = self.outfile.read_bytes()
+        payload = before[before.index(b"\n---") :].split(b"\n", 2)[2]
+        self.assertTrue(after.endswith(payload))
+
+    def test_remove_identity(self):
+        pyrage.rewrap(
+            self.infile,
+            self.outfile,
+            [self.old],
+            [self.new.to_public()],
+            remove=[self.old],
+        )
+
+        encrypted = self.outfile.read_bytes()
+        self.assertEqual(pyrage.decrypt(encrypted, [self.new]), b"test" * 1000)
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.decrypt(encrypted, [self.old])
+
+    def test_remove_all(self):
+        pyrage.rewrap(
+            self.infile, self.outfile, [self.old], [self.new.to_public()], remove="all"
+        )
+
+        encrypted = self.outfile.read_bytes()
+        self.assertEqual(pyrage.decrypt(encrypted, [self.new]), b"test" * 1000)
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.decrypt(encrypted, [self.old])
+
+    def test_remove_all_without_recipients(self):
+        with self.assertRaises(pyrage.EncryptError):
+            pyrage.rewrap(self.infile, self.outfile, [self.old], remove="all")
+        self.assertFalse(self.outfile.exists())
+
+    def test_remove_only_recipient(self):
+        # the grease stanza that's left isn't for anyone
+        with self.assertRaisesRegex(pyrage.EncryptError, "at least one recipient"):
+            pyrage.rewrap(self.infile, self.outfile, [self.old], remove=[self.old])
+        self.assertFalse(self.outfile.exists())
+
+    def test_invalid_remove(self):
+        with self.assertRaisesRegex(ValueError, "invalid remove"):
+            pyrage.rewrap(self.infile, self.outfile, [self.old], remove="some")
+
+    def test_wrong_identity(self):
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.rewrap(self.infile, self.outfile, [self.new], [self.new.to_public()])
+        self.assertFalse(self.outfile.exists())
+
+    def test_wrong_identity_diagnostics(self):
+        with self.assertRaises(pyrage.DecryptError) as cm:
+            pyrage.rewrap(self.infile, self.outfile, [self.new], [self.new.to_public()])
+        message = str(cm.exception)
+        self.assertIn("No matching keys found", message)
+        self.assertIn("encrypted to 2 stanza(s)", message)
+        self.assertRegex(message, r"\n  X25519 \S+\n")
+        self.assertIn("Identities tried: pyrage.x25519.Identity", message)
+
+    def test_wrong_passphrase(self):
+        self.infile.write_bytes(pyrage.passphrase.encrypt(b"test", "secret"))
+
+        with self.assertRaises(pyrage.DecryptError):
+            pyrage.rewrap(
+                self.infile,
+                self.outfile,
+                [],
+                [self.new.to_public()],
+                remove="all",
+                passphrase="wrong",
+            )
+        self.assertFalse(self.outfile.exists())
+
+    def test_armored(self):
+        self.infile.write_bytes(
+            pyrage.encrypt(b"test", [self.old.to_public()], armored=True)
+        )
+        pyrage.rewrap(self.infile, self.outfile, [self.old], [self.new.to_public()])
+
+        encrypted = self.outfile.read_bytes()
+        self.assertTrue(encrypted.startswith(b"-----BEGIN AGE ENCRYPTED FILE-----"))
+        self.assertEqual(pyrage.decrypt(encrypted, [self.new]), b"test")
+
+    def test_format(self):
+        with self.assertRaisesRegex(pyrage.DecryptError, "expected an armored"):
+            pyrage.rewrap(
+                self.infile,
+                self.outfile,
+                [self.old],
+                [self.new.to_public()],
+                format="armored",
+            )
+        self.assertFalse(self.outfile.exists())
+
+        self.infile.write_bytes(pyrage.armor(self.infile.read_bytes()))
+        with self.assertRaisesRegex(pyrage.DecryptError, "expected a binary"):
+            pyrage.rewrap(
+                self.infile,
+                self.outfile,
+                [self.old],
+                [self.new.to_public()],
+                format="binary",
+            )
+        pyrage.rewrap(
+            self.infile,
+            self.outfile,
+            [self.old],
+            [self.new.to_public()],
+            format="armored",
+        )
+        self.assertTrue(pyrage.is_armored(self.outfile.read_bytes()))
+
+    def test_in_place(self):
+        pyrage.rewrap(self.infile, self.infile, [self.old], [self.new.to_public()])
+        self.assertEqual(
+            pyrage.decrypt(self.infile.read_bytes(), [self.new]), b"test" * 1000
+        )
+
+    def test_passphrase(self):
+        self.infile.write_bytes(pyrage.passphrase.encrypt(b"test", "secret"))
+
+        with self.assertRaisesRegex(pyrage.EncryptError, "scrypt"):
+            pyrage.rewrap(
+                self.infile,
+                self.outfile,
+                [],
+                [self.new.to_public()],
+                passphrase="secret",
+            )
+
+        pyrage.rewrap(
+            self.infile,
+            self.outfile,
+            [],
+            [self.new.to_public()],
+            remove="all",
+            passphrase="secret",
+        )
+        self.assertEqual(pyrage.decrypt(self.outfile.read_bytes(), [self.new]), b"test")
+
+    def test_limits(self):
+        for limit in ({"max_header_size": 50}, {"max_stanzas": 0}):
+            with self.subTest(limit=limit):
+                with self.assertRaisesRegex(pyrage.LimitExceededError, next(iter(limit))):
+                    pyrage.rewrap(
+                        self.infile, self.outfile, [self.old], [self.new.to_public()], **limit
+                    )
+                self.assertFalse(self.outfile.exists())
+
+        pyrage.rewrap(
+            self.infile,
+            self.outfile,
+            [self.old],
+            [self.new.to_public()],
+            max_header_size=4096,
+            max_stanzas=5,
+        )
+        self.assertEqual(
+            pyrage.decrypt(self.outfile.read_bytes(), [self.new]), b"test" * 1000
+        )
+
+    def test_limits_work_factor(self):
+        self.infile.write_bytes(pyrage.passphrase.encrypt(b"test", "secret"))
+
+        with self.assertRaisesRegex(pyrage.LimitExceededError, "max_work_factor"):
+            pyrage.rewrap(
+                self.infile,
+                self.outfile,
+                [],
+                [self.new.to_public()],
+                remove="all",
+                passphrase="secret",
+                max_work_factor=1,
+            )
+        self.assertFalse(self.outfile.exists())
+
+        pyrage.rewrap(
+            self.infile,
+            self.outfile,
+            [],
+            [self.new.to_public()],
+            remove="all",
+            passphrase="secret",
+            max_work_factor=22,
+        )
+        self.assertEqual(pyrage.decrypt(self.outfile.read_bytes(), [self.new]), b"test")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -1,6 +1,7 @@
 import unittest
 
-from pyrage import RecipientError, ssh
+import pyrage
+from pyrage import IdentityError, RecipientError, ssh, x25519
 
 from .utils import ssh_keypair
 
@@ -12,6 +13,26 @@ class TestIdentity(unittest.TestCase):
             identity = ssh.Identity.from_buffer(privkey.encode())
             self.assertIsInstance(identity, ssh.Identity)
 
+    def test_to_x25519(self):
+        pubkey, privkey = ssh_keypair("ed25519")
+        identity = ssh.Identity.from_buffer(privkey.encode()).to_x25519()
+        self.assertIsInstance(identity, x25519.Identity)
+        # the same key, converted from the public half alone
+        self.assertEqual(
+            str(identity.to_public()), str(ssh.Recipient.from_str(pubkey).to_x25519())
+        )
+
+        encrypted = pyrage.encrypt(b"test", [identity.to_public()])
+        decrypted, info = pyrage.decrypt(encrypted, [identity], return_info=True)
+        self.assertEqual(decrypted, b"test")
+        self.assertEqual(info.stanza_tag, "X25519")
+
+    def test_to_x25519_rsa(self):
+        _pubkey, privkey = ssh_keypair("rsa2048")
+        identity = ssh.Identity.from_buffer(privkey.encode())
+        with self.assertRaisesRegex(IdentityError, "only ssh-ed25519 keys"):
+            identity.to_x25519()
+
 
 class TestRecipient(unittest.TestCase):
     def test_from_str(self):
@@ -20,6 +41,20 @@ class TestRecipient(unittest.TestCase):
             recipient = ssh.Recipient.from_str(pubkey)
             self.assertIsInstance(recipient, ssh.Recipient)
 
+    def test_to_x25519(self):
+        pubkey, _privkey = ssh_keypair("ed25519")
+        recipient = ssh.Recipient.from_str(pubkey).to_x25519()
+        self.assertIsInstance(recipient, x25519.Recipient)
+        # u = (1 + y) / (1 - y), for the Ed25519 public key's y
+        self.assertEqual(
+            str(recipient),
+            "age1pdjje2s2slwdpfjgd8ehsd5jfdt2v2g6tx3qrekzys9fmzn04das3f5tj9",
+        )
+
+        pubkey, _privkey = ssh_keypair("rsa2048")
+        with self.assertRaisesRegex(RecipientError, "only ssh-ed25519 keys"):
+            ssh.Recipient.from_str(pubkey).to_x25519()
+
     def test_from_str_invalid(self):
         with self.assertRaisesRegex(RecipientError, "invalid SSH recipient"):
             ssh.Recipient.from_str("invalid ssh pubkey")
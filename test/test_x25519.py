@@ -7,15 +7,22 @@ class TestIdentity(unittest.TestCase):
     def test_generate(self):
         identity = x25519.Identity.generate()
         self.assertIsInstance(identity, x25519.Identity)
-        self.assertTrue(str(identity).startswith("AGE-SECRET-KEY"))
+        self.assertTrue(identity.reveal_secret().startswith("AGE-SECRET-KEY"))
 
         recipient = identity.to_public()
         self.assertTrue(str(recipient).startswith("age"))
 
     def test_from_str(self):
         generated = x25519.Identity.generate()
-        parsed = x25519.Identity.from_str(str(generated))
+        parsed = x25519.Identity.from_str(generated.reveal_secret())
         self.assertIsInstance(parsed, x25519.Identity)
+        self.assertEqual(parsed.reveal_secret(), generated.reveal_secret())
+
+    def test_str_hides_secret(self):
+        identity = x25519.Identity.generate()
+        for s in (str(identity), repr(identity)):
+            self.assertNotIn("AGE-SECRET-KEY", s)
+            self.assertEqual(s, f'Identity(public="{identity.to_public()}")')
 
     def test_from_str_invalid(self):
         with self.assertRaisesRegex(IdentityError, "invalid Bech32 encoding"):